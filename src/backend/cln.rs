@@ -0,0 +1,326 @@
+//! Core Lightning backend, talking to lightningd over its unix socket RPC
+
+use async_trait::async_trait;
+use cln_rpc::{
+    model::{requests as creq, responses as cresp},
+    primitives::{Amount, AmountOrAll, AmountOrAny, ChannelState as ClnChannelState, Feerate, Sha256},
+    ClnRpc, RpcError,
+};
+use secp256k1::PublicKey;
+use std::str::FromStr;
+use tokio::sync::Mutex;
+
+use super::*;
+
+pub struct ClnBackend {
+    rpc: Mutex<ClnRpc>,
+}
+
+impl ClnBackend {
+    /// Connect to lightningd through its `lightning-rpc` socket
+    pub async fn connect(rpc_path: &str) -> BackendResult<Self> {
+        let rpc = ClnRpc::new(rpc_path)
+            .await
+            .map_err(|e| BackendError::Unavailable(e.to_string()))?;
+
+        Ok(ClnBackend { rpc: Mutex::new(rpc) })
+    }
+
+    async fn call<R>(&self, req: &R) -> BackendResult<R::Response>
+    where
+        R: cln_rpc::TypedRequest + serde::Serialize + fmt::Debug,
+        R::Response: serde::de::DeserializeOwned + fmt::Debug,
+    {
+        let mut guard = self.rpc.lock().await;
+        guard.call_typed(req).await.map_err(BackendError::from)
+    }
+}
+
+impl From<RpcError> for BackendError {
+    fn from(e: RpcError) -> Self {
+        BackendError::Rpc {
+            code: e.code,
+            message: e.message,
+        }
+    }
+}
+
+fn address_kind(kind: cresp::GetinfoAddressType) -> AddressKind {
+    match kind {
+        cresp::GetinfoAddressType::DNS => AddressKind::Dns,
+        cresp::GetinfoAddressType::IPV4 => AddressKind::Ipv4,
+        cresp::GetinfoAddressType::IPV6 => AddressKind::Ipv6,
+        cresp::GetinfoAddressType::TORV2 => AddressKind::TorV2,
+        cresp::GetinfoAddressType::TORV3 => AddressKind::TorV3,
+    }
+}
+
+fn channel_state(state: ClnChannelState) -> ChannelState {
+    match state {
+        ClnChannelState::CHANNELD_NORMAL | ClnChannelState::CHANNELD_AWAITING_SPLICE => ChannelState::Normal,
+        ClnChannelState::CHANNELD_SHUTTING_DOWN
+        | ClnChannelState::CLOSINGD_SIGEXCHANGE
+        | ClnChannelState::CLOSINGD_COMPLETE
+        | ClnChannelState::AWAITING_UNILATERAL
+        | ClnChannelState::FUNDING_SPEND_SEEN => ChannelState::Closing,
+        ClnChannelState::ONCHAIN => ChannelState::Closed,
+        // openingd, dualopend and channeld before lockin
+        _ => ChannelState::AwaitingLockin,
+    }
+}
+
+#[async_trait]
+impl LightningBackend for ClnBackend {
+    async fn get_info(&self) -> BackendResult<NodeInfo> {
+        let resp: cresp::GetinfoResponse = self.call(&creq::GetinfoRequest {}).await?;
+
+        let addresses = resp
+            .address
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|a| {
+                Some(NodeAddress {
+                    kind: address_kind(a.item_type),
+                    address: a.address?,
+                    port: a.port,
+                })
+            })
+            .collect();
+
+        Ok(NodeInfo {
+            id: resp.id,
+            alias: resp.alias,
+            network: resp.network,
+            block_height: resp.blockheight,
+            addresses,
+        })
+    }
+
+    async fn connect_peer(&self, node_id: &PublicKey, host: Option<&str>, port: Option<u16>) -> BackendResult<()> {
+        let req = creq::ConnectRequest {
+            id: node_id.to_string(),
+            host: host.map(str::to_string),
+            port,
+        };
+
+        let _resp: cresp::ConnectResponse = self.call(&req).await?;
+        Ok(())
+    }
+
+    async fn is_connected(&self, node_id: &PublicKey) -> BackendResult<bool> {
+        let req = creq::ListpeersRequest {
+            id: Some(*node_id),
+            level: None,
+        };
+
+        let resp: cresp::ListpeersResponse = self.call(&req).await?;
+        Ok(resp.peers.iter().any(|peer| peer.connected))
+    }
+
+    async fn fund_channel(&self, params: FundChannelParams) -> BackendResult<FundedChannel> {
+        let req = creq::FundchannelRequest {
+            id: params.node_id,
+            amount: AmountOrAll::Amount(Amount::from_sat(params.amount_sat)),
+            feerate: params.feerate.as_deref().map(Feerate::try_from).transpose().map_err(|e| BackendError::Rpc {
+                code: None,
+                message: format!("invalid feerate: {}", e),
+            })?,
+            announce: Some(params.announce),
+            channel_type: params.channel_type.feature_bits(),
+            minconf: params.minconf,
+            utxos: None,
+            push_msat: (params.push_msat > 0).then(|| Amount::from_msat(params.push_msat)),
+            close_to: None,
+            request_amt: params.request_amt_sat.map(Amount::from_sat),
+            compact_lease: params.compact_lease,
+            reserve: params.reserve_sat.map(Amount::from_sat),
+            mindepth: (params.channel_type == ChannelType::ZeroConf).then_some(0),
+        };
+
+        let resp: cresp::FundchannelResponse = self.call(&req).await?;
+        Ok(FundedChannel {
+            channel_id: resp.channel_id.to_string(),
+            txid: resp.txid,
+            outnum: resp.outnum,
+            tx: resp.tx,
+            mindepth: resp.mindepth,
+        })
+    }
+
+    async fn lease_rates(&self, node_id: &PublicKey) -> BackendResult<Option<LeaseRates>> {
+        let req = creq::ListnodesRequest { id: Some(*node_id) };
+
+        let resp: cresp::ListnodesResponse = self.call(&req).await?;
+        let rates = resp.nodes.into_iter().find_map(|node| node.option_will_fund).map(|ad| LeaseRates {
+            compact_lease: ad.compact_lease,
+            lease_fee_base_msat: ad.lease_fee_base_msat.msat(),
+            lease_fee_basis: ad.lease_fee_basis,
+            funding_weight: ad.funding_weight,
+            channel_fee_max_base_msat: ad.channel_fee_max_base_msat.msat(),
+            channel_fee_max_proportional_thousandths: ad.channel_fee_max_proportional_thousandths,
+        });
+        Ok(rates)
+    }
+
+    async fn list_channels(&self) -> BackendResult<Vec<ChannelInfo>> {
+        let req = creq::ListpeerchannelsRequest {
+            id: None,
+            short_channel_id: None,
+        };
+
+        let resp: cresp::ListpeerchannelsResponse = self.call(&req).await?;
+        let channels = resp
+            .channels
+            .into_iter()
+            .filter_map(|c| {
+                Some(ChannelInfo {
+                    channel_id: c.channel_id?.to_string(),
+                    peer_id: c.peer_id,
+                    state: channel_state(c.state),
+                    short_channel_id: c.short_channel_id.map(|scid| scid.to_string()),
+                })
+            })
+            .collect();
+        Ok(channels)
+    }
+
+    async fn onchain_balance(&self) -> BackendResult<OnchainBalance> {
+        let resp: cresp::ListfundsResponse = self.call(&creq::ListfundsRequest { spent: None }).await?;
+
+        let mut balance = OnchainBalance::default();
+        for output in resp.outputs.iter().filter(|o| !o.reserved) {
+            let sat = output.amount_msat.msat() / 1000;
+            match output.status {
+                cresp::ListfundsOutputsStatus::CONFIRMED => balance.confirmed_sat += sat,
+                cresp::ListfundsOutputsStatus::UNCONFIRMED | cresp::ListfundsOutputsStatus::IMMATURE => {
+                    balance.unconfirmed_sat += sat
+                }
+                cresp::ListfundsOutputsStatus::SPENT => {}
+            }
+        }
+        Ok(balance)
+    }
+
+    async fn create_invoice(&self, params: InvoiceParams) -> BackendResult<Invoice> {
+        let req = creq::InvoiceRequest {
+            amount_msat: AmountOrAny::Amount(Amount::from_msat(params.amount_msat)),
+            description: params.description,
+            label: params.label,
+            expiry: params.expiry,
+            fallbacks: None,
+            preimage: None,
+            cltv: None,
+            deschashonly: Some(params.description_hash_only),
+            exposeprivatechannels: None,
+        };
+
+        let resp: cresp::InvoiceResponse = self.call(&req).await?;
+        Ok(Invoice {
+            bolt11: resp.bolt11,
+            payment_hash: resp.payment_hash.to_string(),
+            expires_at: resp.expires_at,
+        })
+    }
+
+    async fn decode_invoice(&self, bolt11: &str) -> BackendResult<DecodedInvoice> {
+        let req = creq::DecodepayRequest {
+            bolt11: bolt11.to_string(),
+            description: None,
+        };
+
+        let resp: cresp::DecodepayResponse = self.call(&req).await?;
+        Ok(DecodedInvoice {
+            payee: resp.payee,
+            payment_hash: resp.payment_hash.to_string(),
+            amount_msat: resp.amount_msat.map(|a| a.msat()),
+            description: resp.description,
+            description_hash: resp.description_hash,
+            currency: resp.currency,
+            created_at: resp.created_at,
+            expiry: resp.expiry,
+        })
+    }
+
+    async fn pay_invoice(&self, params: PayInvoiceParams) -> BackendResult<Payment> {
+        let req = creq::PayRequest {
+            bolt11: params.bolt11,
+            amount_msat: None,
+            label: None,
+            riskfactor: None,
+            maxfeepercent: None,
+            retry_for: params.retry_for_secs,
+            maxdelay: params.max_delay,
+            exemptfee: None,
+            localinvreqid: None,
+            exclude: (!params.exclude.is_empty()).then_some(params.exclude),
+            maxfee: params.max_fee_msat.map(Amount::from_msat),
+            description: None,
+            partial_msat: None,
+        };
+
+        let resp: cresp::PayResponse = self.call(&req).await?;
+        let status = match resp.status {
+            cresp::PayStatus::COMPLETE => PaymentStatus::Complete,
+            cresp::PayStatus::PENDING => PaymentStatus::Pending,
+            cresp::PayStatus::FAILED => PaymentStatus::Failed,
+        };
+
+        Ok(Payment {
+            status,
+            payment_hash: resp.payment_hash.to_string(),
+            preimage: Some(hex::encode(resp.payment_preimage.to_vec())),
+            amount_msat: resp.amount_msat.msat(),
+            amount_sent_msat: resp.amount_sent_msat.msat(),
+        })
+    }
+
+    async fn payment_status(&self, payment_hash: &str) -> BackendResult<Option<Payment>> {
+        let req = creq::ListpaysRequest {
+            bolt11: None,
+            payment_hash: Some(Sha256::from_str(payment_hash).map_err(|e| BackendError::Rpc {
+                code: None,
+                message: format!("invalid payment hash: {}", e),
+            })?),
+            status: None,
+            index: None,
+            start: None,
+            limit: None,
+        };
+
+        let resp: cresp::ListpaysResponse = self.call(&req).await?;
+        // One entry per pay attempt, a complete one settles it and a pending one may still do so
+        let rank = |status: &cresp::ListpaysPaysStatus| match status {
+            cresp::ListpaysPaysStatus::COMPLETE => 2,
+            cresp::ListpaysPaysStatus::PENDING => 1,
+            cresp::ListpaysPaysStatus::FAILED => 0,
+        };
+        let Some(pay) = resp.pays.into_iter().max_by_key(|pay| rank(&pay.status)) else {
+            return Ok(None);
+        };
+
+        let status = match pay.status {
+            cresp::ListpaysPaysStatus::COMPLETE => PaymentStatus::Complete,
+            cresp::ListpaysPaysStatus::PENDING => PaymentStatus::Pending,
+            cresp::ListpaysPaysStatus::FAILED => PaymentStatus::Failed,
+        };
+        let amount_msat = pay.amount_msat.map_or(0, |a| a.msat());
+        Ok(Some(Payment {
+            status,
+            payment_hash: pay.payment_hash.to_string(),
+            preimage: pay.preimage.map(|p| hex::encode(p.to_vec())),
+            amount_msat,
+            amount_sent_msat: pay.amount_sent_msat.map_or(amount_msat, |a| a.msat()),
+        }))
+    }
+
+    async fn check_message(&self, message: &str, zbase: &str, pubkey: &PublicKey) -> BackendResult<bool> {
+        let req = creq::CheckmessageRequest {
+            message: message.to_string(),
+            zbase: zbase.to_string(),
+            pubkey: Some(*pubkey),
+        };
+
+        let resp: cresp::CheckmessageResponse = self.call(&req).await?;
+        Ok(resp.verified)
+    }
+}
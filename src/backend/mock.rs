@@ -0,0 +1,479 @@
+//! Deterministic in-memory Lightning node, used to run the flows offline
+//!
+//! Invoices are self-describing (`lnmock` + hex JSON) so any `MockBackend`
+//! can decode an invoice created by another one, like a wallet node and a
+//! service node would. Every call can be configured to fail.
+
+use async_trait::async_trait;
+use secp256k1::hashes::{sha256, Hash};
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use super::*;
+
+pub const MOCK_INVOICE_PREFIX: &str = "lnmock";
+pub const MOCK_ONCHAIN_SAT: u64 = 100_000_000; // confirmed funds of a new node
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MockCall {
+    GetInfo,
+    ConnectPeer,
+    ListPeers,
+    FundChannel,
+    LeaseRates,
+    ListChannels,
+    ListFunds,
+    CreateInvoice,
+    DecodeInvoice,
+    PayInvoice,
+    ListPays,
+}
+
+type FailureFn = Box<dyn Fn() -> BackendError + Send>;
+
+struct MockFailure {
+    remaining: Option<usize>, // None fails forever
+    make_error: FailureFn,
+}
+
+#[derive(Default)]
+struct MockState {
+    failures: HashMap<MockCall, MockFailure>,
+    addresses: Vec<NodeAddress>,
+    peers: Vec<PublicKey>,
+    channels: Vec<FundChannelParams>,
+    channel_states: Vec<ChannelInfo>, // of the funded channels, same order
+    lease_rates: HashMap<PublicKey, LeaseRates>, // liquidity ads seen in gossip
+    onchain: OnchainBalance, // funding transactions spend confirmed funds
+    invoices: Vec<Invoice>,
+    payments: Vec<Payment>,
+    pay_requests: Vec<PayInvoiceParams>,
+    hold_payments: bool, // payments stay pending until settled
+    lost_pay_responses: usize, // pay sends the payment then loses the connection
+}
+
+#[derive(Serialize, Deserialize)]
+struct MockInvoice {
+    payee: String,
+    payment_hash: String,
+    amount_msat: Option<u64>,
+    description: Option<String>,
+    description_hash: Option<String>,
+    currency: String,
+    created_at: u64,
+    expiry: u64,
+}
+
+pub struct MockBackend {
+    id: PublicKey,
+    network: String,
+    block_height: u32,
+    routing_fee_msat: u64, // charged on every payment
+    state: Mutex<MockState>,
+}
+
+fn digest(parts: &[&[u8]]) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    for part in parts {
+        secp256k1::hashes::HashEngine::input(&mut engine, part);
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+fn invalid_invoice() -> BackendError {
+    BackendError::Rpc {
+        code: Some(-32602),
+        message: "Invalid bolt11".to_string(),
+    }
+}
+
+impl MockBackend {
+    /// A testnet4 node whose key is derived from `seed`, so ids are stable across runs
+    pub fn new(seed: u8) -> Self {
+        let secret = SecretKey::from_slice(digest(&[b"mock-node", &[seed]]).as_byte_array())
+            .expect("sha256 output is a valid secret key");
+
+        MockBackend {
+            id: PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret),
+            network: "testnet4".to_string(),
+            block_height: 100_000,
+            routing_fee_msat: 0,
+            state: Mutex::new(MockState {
+                onchain: OnchainBalance {
+                    confirmed_sat: MOCK_ONCHAIN_SAT,
+                    unconfirmed_sat: 0,
+                },
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn with_network(mut self, network: &str) -> Self {
+        self.network = network.to_string();
+        self
+    }
+
+    pub fn with_address(self, address: NodeAddress) -> Self {
+        self.state.lock().unwrap().addresses.push(address);
+        self
+    }
+
+    pub fn with_routing_fee(mut self, fee_msat: u64) -> Self {
+        self.routing_fee_msat = fee_msat;
+        self
+    }
+
+    pub fn node_id(&self) -> PublicKey {
+        self.id
+    }
+
+    /// Make every future `call` fail with `message`
+    pub fn fail(&self, call: MockCall, message: &str) {
+        let message = message.to_string();
+        self.fail_with(call, None, move || BackendError::Rpc {
+            code: None,
+            message: message.clone(),
+        });
+    }
+
+    /// Make the next `call` fail with `message`, later ones succeed
+    pub fn fail_once(&self, call: MockCall, message: &str) {
+        let message = message.to_string();
+        self.fail_with(call, Some(1), move || BackendError::Rpc {
+            code: None,
+            message: message.clone(),
+        });
+    }
+
+    /// Make the next `times` calls (all of them when `None`) return `make_error()`
+    pub fn fail_with<F>(&self, call: MockCall, times: Option<usize>, make_error: F)
+    where
+        F: Fn() -> BackendError + Send + 'static,
+    {
+        let failure = MockFailure {
+            remaining: times,
+            make_error: Box::new(make_error),
+        };
+        self.state.lock().unwrap().failures.insert(call, failure);
+    }
+
+    pub fn clear_failures(&self) {
+        self.state.lock().unwrap().failures.clear();
+    }
+
+    /// Record `node_id` as connected, like a wallet connecting to us would
+    pub fn add_peer(&self, node_id: &PublicKey) {
+        let mut state = self.state.lock().unwrap();
+        if !state.peers.contains(node_id) {
+            state.peers.push(*node_id);
+        }
+    }
+
+    pub fn peers(&self) -> Vec<PublicKey> {
+        self.state.lock().unwrap().peers.clone()
+    }
+
+    pub fn channels(&self) -> Vec<FundChannelParams> {
+        self.state.lock().unwrap().channels.clone()
+    }
+
+    /// Gossip a liquidity ad of `node_id`
+    pub fn set_lease_rates(&self, node_id: &PublicKey, rates: LeaseRates) {
+        self.state.lock().unwrap().lease_rates.insert(*node_id, rates);
+    }
+
+    pub fn set_onchain_balance(&self, balance: OnchainBalance) {
+        self.state.lock().unwrap().onchain = balance;
+    }
+
+    /// Move a funded channel to `state`, it gets a short channel id once normal
+    pub fn set_channel_state(&self, channel_id: &str, state: ChannelState) {
+        let mut mock = self.state.lock().unwrap();
+        let Some(index) = mock.channel_states.iter().position(|c| c.channel_id == channel_id) else {
+            return;
+        };
+
+        let channel = &mut mock.channel_states[index];
+        channel.state = state;
+        if state == ChannelState::Normal {
+            channel.short_channel_id.get_or_insert(format!("{}x{}x0", self.block_height, index + 1));
+        }
+    }
+
+    pub fn invoices(&self) -> Vec<Invoice> {
+        self.state.lock().unwrap().invoices.clone()
+    }
+
+    pub fn payments(&self) -> Vec<Payment> {
+        self.state.lock().unwrap().payments.clone()
+    }
+
+    /// Keep the next payments pending (HTLCs stuck in flight) until `settle_payments`
+    pub fn hold_payments(&self, hold: bool) {
+        self.state.lock().unwrap().hold_payments = hold;
+    }
+
+    /// The next `times` payments are sent but their caller gets a connection error
+    pub fn lose_pay_responses(&self, times: usize) {
+        self.state.lock().unwrap().lost_pay_responses = times;
+    }
+
+    /// Resolve every pending payment to `status`
+    pub fn settle_payments(&self, status: PaymentStatus) {
+        let mut state = self.state.lock().unwrap();
+        for payment in state.payments.iter_mut().filter(|p| p.status == PaymentStatus::Pending) {
+            payment.status = status;
+        }
+    }
+
+    /// Every `pay_invoice` call, failed ones included
+    pub fn pay_requests(&self) -> Vec<PayInvoiceParams> {
+        self.state.lock().unwrap().pay_requests.clone()
+    }
+
+    fn check_failure(&self, call: MockCall) -> BackendResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let Some(failure) = state.failures.get_mut(&call) else {
+            return Ok(());
+        };
+
+        let error = (failure.make_error)();
+        match &mut failure.remaining {
+            Some(1) => {
+                state.failures.remove(&call);
+            }
+            Some(n) => *n -= 1,
+            None => {}
+        }
+        Err(error)
+    }
+
+    fn decode(bolt11: &str) -> BackendResult<MockInvoice> {
+        let encoded = bolt11
+            .strip_prefix(MOCK_INVOICE_PREFIX)
+            .ok_or_else(invalid_invoice)?;
+        let bytes = hex::decode(encoded).map_err(|_| invalid_invoice())?;
+        serde_json::from_slice(&bytes).map_err(|_| invalid_invoice())
+    }
+}
+
+#[async_trait]
+impl LightningBackend for MockBackend {
+    async fn get_info(&self) -> BackendResult<NodeInfo> {
+        self.check_failure(MockCall::GetInfo)?;
+
+        Ok(NodeInfo {
+            id: self.id,
+            alias: Some("mock".to_string()),
+            network: self.network.clone(),
+            block_height: self.block_height,
+            addresses: self.state.lock().unwrap().addresses.clone(),
+        })
+    }
+
+    async fn connect_peer(&self, node_id: &PublicKey, _host: Option<&str>, _port: Option<u16>) -> BackendResult<()> {
+        self.check_failure(MockCall::ConnectPeer)?;
+
+        self.add_peer(node_id);
+        Ok(())
+    }
+
+    async fn is_connected(&self, node_id: &PublicKey) -> BackendResult<bool> {
+        self.check_failure(MockCall::ListPeers)?;
+
+        Ok(self.state.lock().unwrap().peers.contains(node_id))
+    }
+
+    async fn fund_channel(&self, params: FundChannelParams) -> BackendResult<FundedChannel> {
+        self.check_failure(MockCall::FundChannel)?;
+
+        let mut state = self.state.lock().unwrap();
+        if !state.peers.contains(&params.node_id) {
+            return Err(BackendError::Rpc {
+                code: Some(306),
+                message: "Unknown peer".to_string(),
+            });
+        }
+        if params.request_amt_sat.is_some() {
+            let advertised = state.lease_rates.get(&params.node_id).map(|rates| &rates.compact_lease);
+            if advertised.is_none() || advertised != params.compact_lease.as_ref() {
+                return Err(BackendError::Rpc {
+                    code: Some(-32602),
+                    message: "Peer's lease rates do not match compact_lease".to_string(),
+                });
+            }
+        }
+        if params.amount_sat > state.onchain.confirmed_sat {
+            return Err(BackendError::Rpc {
+                code: Some(301),
+                message: format!("Could not afford {}sat using all 1 available UTXOs", params.amount_sat),
+            });
+        }
+        state.onchain.confirmed_sat -= params.amount_sat;
+        let index = state.channels.len() as u64;
+        let txid = digest(&[b"funding", &params.node_id.serialize(), &index.to_be_bytes()]);
+        let channel_id = digest(&[b"channel", txid.as_byte_array()]).to_string();
+        state.channel_states.push(ChannelInfo {
+            channel_id: channel_id.clone(),
+            peer_id: params.node_id,
+            state: ChannelState::AwaitingLockin,
+            short_channel_id: None,
+        });
+        state.channels.push(params);
+
+        Ok(FundedChannel {
+            channel_id,
+            txid: txid.to_string(),
+            outnum: 0,
+            tx: format!("02000000{}", txid),
+            mindepth: Some(3),
+        })
+    }
+
+    async fn lease_rates(&self, node_id: &PublicKey) -> BackendResult<Option<LeaseRates>> {
+        self.check_failure(MockCall::LeaseRates)?;
+
+        Ok(self.state.lock().unwrap().lease_rates.get(node_id).cloned())
+    }
+
+    async fn list_channels(&self) -> BackendResult<Vec<ChannelInfo>> {
+        self.check_failure(MockCall::ListChannels)?;
+
+        Ok(self.state.lock().unwrap().channel_states.clone())
+    }
+
+    async fn onchain_balance(&self) -> BackendResult<OnchainBalance> {
+        self.check_failure(MockCall::ListFunds)?;
+
+        Ok(self.state.lock().unwrap().onchain)
+    }
+
+    async fn create_invoice(&self, params: InvoiceParams) -> BackendResult<Invoice> {
+        self.check_failure(MockCall::CreateInvoice)?;
+
+        let payment_hash = digest(&[b"invoice", &self.id.serialize(), params.label.as_bytes()]);
+        let (description, description_hash) = if params.description_hash_only {
+            (None, Some(sha256::Hash::hash(params.description.as_bytes()).to_string()))
+        } else {
+            (Some(params.description), None)
+        };
+
+        let created_at = chrono::Utc::now().timestamp() as u64;
+        let expiry = params.expiry.unwrap_or(3600);
+        let mock = MockInvoice {
+            payee: self.id.to_string(),
+            payment_hash: payment_hash.to_string(),
+            amount_msat: Some(params.amount_msat),
+            description,
+            description_hash,
+            currency: bolt11_currency(&self.network).to_string(),
+            created_at,
+            expiry,
+        };
+
+        let json = serde_json::to_vec(&mock).expect("mock invoice is serializable");
+        let invoice = Invoice {
+            bolt11: format!("{}{}", MOCK_INVOICE_PREFIX, hex::encode(json)),
+            payment_hash: mock.payment_hash,
+            expires_at: created_at + expiry,
+        };
+
+        self.state.lock().unwrap().invoices.push(invoice.clone());
+        Ok(invoice)
+    }
+
+    async fn decode_invoice(&self, bolt11: &str) -> BackendResult<DecodedInvoice> {
+        self.check_failure(MockCall::DecodeInvoice)?;
+
+        let mock = Self::decode(bolt11)?;
+        Ok(DecodedInvoice {
+            payee: PublicKey::from_str(&mock.payee).map_err(|_| invalid_invoice())?,
+            payment_hash: mock.payment_hash,
+            amount_msat: mock.amount_msat,
+            description: mock.description,
+            description_hash: match mock.description_hash {
+                Some(hash) => Some(Sha256::from_str(&hash).map_err(|_| invalid_invoice())?),
+                None => None,
+            },
+            currency: mock.currency,
+            created_at: mock.created_at,
+            expiry: mock.expiry,
+        })
+    }
+
+    async fn pay_invoice(&self, params: PayInvoiceParams) -> BackendResult<Payment> {
+        self.state.lock().unwrap().pay_requests.push(params.clone());
+        self.check_failure(MockCall::PayInvoice)?;
+
+        let mock = Self::decode(&params.bolt11)?;
+        if mock.payee == self.id.to_string() {
+            return Err(BackendError::Rpc {
+                code: Some(-1),
+                message: "cannot pay ourselves".to_string(),
+            });
+        }
+        let amount_msat = mock.amount_msat.ok_or_else(|| BackendError::Rpc {
+            code: Some(-32602),
+            message: "amount_msat parameter required".to_string(),
+        })?;
+
+        // Single hop routes, excluding the payee leaves none
+        if params.exclude.contains(&mock.payee) {
+            return Err(BackendError::Rpc {
+                code: Some(205),
+                message: "Could not find a route".to_string(),
+            });
+        }
+        if params.max_fee_msat.is_some_and(|max| self.routing_fee_msat > max) {
+            return Err(BackendError::Rpc {
+                code: Some(206),
+                message: format!("Route wanted fee of {}msat", self.routing_fee_msat),
+            });
+        }
+
+        let mut state = self.state.lock().unwrap();
+        // Like Core Lightning, an invoice is never paid twice
+        if let Some(previous) = state.payments.iter().rev().find(|p| p.payment_hash == mock.payment_hash) {
+            match previous.status {
+                PaymentStatus::Complete => return Ok(previous.clone()),
+                PaymentStatus::Pending => {
+                    return Err(BackendError::Rpc {
+                        code: Some(-1),
+                        message: "Payment is still in progress".to_string(),
+                    })
+                }
+                PaymentStatus::Failed => {}
+            }
+        }
+        let preimage = digest(&[b"preimage", mock.payment_hash.as_bytes()]);
+        let payment = Payment {
+            status: if state.hold_payments { PaymentStatus::Pending } else { PaymentStatus::Complete },
+            payment_hash: mock.payment_hash,
+            preimage: Some(preimage.to_string()),
+            amount_msat,
+            amount_sent_msat: amount_msat + self.routing_fee_msat,
+        };
+
+        state.payments.push(payment.clone());
+        if state.lost_pay_responses > 0 {
+            state.lost_pay_responses -= 1;
+            return Err(BackendError::Unavailable("connection reset by peer".to_string()));
+        }
+        Ok(payment)
+    }
+
+    async fn payment_status(&self, payment_hash: &str) -> BackendResult<Option<Payment>> {
+        self.check_failure(MockCall::ListPays)?;
+
+        let rank = |payment: &&Payment| match payment.status {
+            PaymentStatus::Complete => 2,
+            PaymentStatus::Pending => 1,
+            PaymentStatus::Failed => 0,
+        };
+        let state = self.state.lock().unwrap();
+        Ok(state.payments.iter().filter(|p| p.payment_hash == payment_hash).max_by_key(rank).cloned())
+    }
+}
@@ -0,0 +1,309 @@
+//! Lightning node abstraction used by the LNURL server and client
+//!
+//! Handlers only talk to a `LightningBackend`, so the same flows can run
+//! against Core Lightning, another implementation, or a fake node in tests.
+
+use async_trait::async_trait;
+use cln_rpc::primitives::Sha256;
+use secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+pub mod cln;
+pub mod mock;
+
+pub use cln::ClnBackend;
+pub use mock::{MockBackend, MockCall};
+
+#[derive(Debug)]
+pub enum BackendError {
+    /// The node could not be reached
+    Unavailable(String),
+    /// The node answered the call with an error
+    Rpc { code: Option<i32>, message: String },
+    /// The backend does not implement this call
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::Unavailable(e) => write!(f, "lightning node unavailable: {}", e),
+            BackendError::Rpc { code: Some(code), message } => write!(f, "error {}: {}", code, message),
+            BackendError::Rpc { code: None, message } => write!(f, "{}", message),
+            BackendError::Unsupported(call) => write!(f, "{} is not supported by this backend", call),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl BackendError {
+    /// Whether a failed `pay_invoice` left nothing in flight, so paying again is safe
+    /// (Core Lightning pay: 205 no route, 206 route too expensive, 210 timed out)
+    pub fn is_retryable_payment(&self) -> bool {
+        match self {
+            BackendError::Unavailable(_) => true,
+            BackendError::Rpc { code, .. } => matches!(code, Some(205 | 206 | 210)),
+            BackendError::Unsupported(_) => false,
+        }
+    }
+
+    /// Whether a failed `fund_channel` broadcast nothing and may succeed later
+    /// (Core Lightning fundchannel: 301 not enough funds, 304 still syncing,
+    /// 305 peer not connected, 306 unknown peer)
+    pub fn is_retryable_funding(&self) -> bool {
+        match self {
+            BackendError::Unavailable(_) => true,
+            BackendError::Rpc { code, .. } => matches!(code, Some(301 | 304 | 305 | 306)),
+            BackendError::Unsupported(_) => false,
+        }
+    }
+}
+
+pub type BackendResult<T> = Result<T, BackendError>;
+
+/// BOLT11 currency prefix of the invoices of a network (`DecodedInvoice::currency`)
+pub fn bolt11_currency(network: &str) -> &'static str {
+    match network {
+        "bitcoin" => "bc",
+        "regtest" => "bcrt",
+        "signet" => "tbs",
+        _ => "tb", // testnet, testnet4
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressKind {
+    Dns,
+    Ipv4,
+    Ipv6,
+    TorV2,
+    TorV3,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeAddress {
+    pub kind: AddressKind,
+    pub address: String,
+    pub port: u16,
+}
+
+#[derive(Clone, Debug)]
+pub struct NodeInfo {
+    pub id: PublicKey,
+    pub alias: Option<String>,
+    pub network: String,
+    pub block_height: u32,
+    pub addresses: Vec<NodeAddress>, // announced addresses
+}
+
+/// Features of a new channel, on top of what the node negotiates by default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelType {
+    #[default]
+    Default,
+    Anchors,
+    ZeroConf, // anchors, usable before the funding transaction confirms
+}
+
+impl ChannelType {
+    /// BOLT 9 feature bits of the `channel_type`, `None` lets the node pick
+    pub fn feature_bits(&self) -> Option<Vec<u32>> {
+        match self {
+            ChannelType::Default => None,
+            ChannelType::Anchors => Some(vec![12, 22]), // static_remotekey, anchors_zero_fee_htlc_tx
+            ChannelType::ZeroConf => Some(vec![12, 22, 46, 50]), // + scid_alias, zeroconf
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FundChannelParams {
+    pub node_id: PublicKey,
+    pub amount_sat: u64,
+    pub announce: bool,
+    pub push_msat: u64, // given to the peer at opening
+    pub feerate: Option<String>, // slow, normal, urgent, <n>perkw or <n>perkb
+    pub channel_type: ChannelType,
+    pub minconf: Option<u32>, // confirmations of the funding utxos
+    pub reserve_sat: Option<u64>, // reserve the peer must keep
+    pub request_amt_sat: Option<u64>, // inbound liquidity leased from the peer (dual-funding)
+    pub compact_lease: Option<String>, // the peer's lease rates we accept, with request_amt_sat
+}
+
+/// Liquidity ad of a node (option_will_fund): what leasing its funds costs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeaseRates {
+    pub compact_lease: String, // given back to fundchannel to accept these rates
+    pub lease_fee_base_msat: u64,
+    pub lease_fee_basis: u32, // in basis points of the leased amount
+    pub funding_weight: u32, // of the peer's inputs, the opener pays their fees on top
+    pub channel_fee_max_base_msat: u64, // routing fees the peer may charge during the lease
+    pub channel_fee_max_proportional_thousandths: u32,
+}
+
+impl LeaseRates {
+    /// Lease fee of `amount_sat` of inbound liquidity, funding weight fees excluded
+    pub fn lease_fee_msat(&self, amount_sat: u64) -> u64 {
+        let proportional = amount_sat.saturating_mul(1000).saturating_mul(self.lease_fee_basis as u64) / 10_000;
+        self.lease_fee_base_msat.saturating_add(proportional)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FundedChannel {
+    pub channel_id: String,
+    pub txid: String,
+    pub outnum: u32,
+    pub tx: String,
+    pub mindepth: Option<u32>,
+}
+
+/// Where a channel is in its life: awaiting lockin → normal → closing → closed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelState {
+    AwaitingLockin, // funding transaction not confirmed deep enough yet
+    Normal,
+    Closing, // mutual or unilateral close in progress
+    Closed, // funding output spent and settled, or forgotten by the node
+}
+
+impl ChannelState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelState::AwaitingLockin => "awaiting_lockin",
+            ChannelState::Normal => "normal",
+            ChannelState::Closing => "closing",
+            ChannelState::Closed => "closed",
+        }
+    }
+}
+
+impl fmt::Display for ChannelState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ChannelState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "awaiting_lockin" => Ok(ChannelState::AwaitingLockin),
+            "normal" => Ok(ChannelState::Normal),
+            "closing" => Ok(ChannelState::Closing),
+            "closed" => Ok(ChannelState::Closed),
+            _ => Err(format!("unknown channel state {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChannelInfo {
+    pub channel_id: String,
+    pub peer_id: PublicKey,
+    pub state: ChannelState,
+    pub short_channel_id: Option<String>, // once the funding transaction confirmed
+}
+
+/// Funds of the node's on-chain wallet, reserved outputs excluded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OnchainBalance {
+    pub confirmed_sat: u64,
+    pub unconfirmed_sat: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct InvoiceParams {
+    pub amount_msat: u64,
+    pub description: String,
+    pub label: String,
+    pub expiry: Option<u64>, // in seconds
+    pub description_hash_only: bool, // commit to sha256(description) instead of the text
+}
+
+#[derive(Clone, Debug)]
+pub struct Invoice {
+    pub bolt11: String,
+    pub payment_hash: String,
+    pub expires_at: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct DecodedInvoice {
+    pub payee: PublicKey,
+    pub payment_hash: String,
+    pub amount_msat: Option<u64>,
+    pub description: Option<String>,
+    pub description_hash: Option<Sha256>,
+    pub currency: String, // "bc", "tb", "bcrt", ...
+    pub created_at: u64,
+    pub expiry: u64, // in seconds after created_at
+}
+
+#[derive(Clone, Debug)]
+pub struct PayInvoiceParams {
+    pub bolt11: String,
+    pub max_fee_msat: Option<u64>, // routing fees allowed, the node default when unset
+    pub retry_for_secs: Option<u16>, // how long the node keeps trying routes
+    pub max_delay: Option<u16>, // CLTV delay allowed, in blocks
+    pub exclude: Vec<String>, // short channel ids (scid/direction) or node ids to route around
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaymentStatus {
+    Complete,
+    Pending,
+    Failed,
+}
+
+#[derive(Clone, Debug)]
+pub struct Payment {
+    pub status: PaymentStatus,
+    pub payment_hash: String,
+    pub preimage: Option<String>,
+    pub amount_msat: u64,
+    pub amount_sent_msat: u64, // amount_msat plus routing fees
+}
+
+#[async_trait]
+pub trait LightningBackend: Send + Sync {
+    async fn get_info(&self) -> BackendResult<NodeInfo>;
+
+    /// Connect to a peer, using the gossip addresses when `host` is `None`
+    async fn connect_peer(&self, node_id: &PublicKey, host: Option<&str>, port: Option<u16>) -> BackendResult<()>;
+
+    /// Whether `node_id` currently has a connection with the node
+    async fn is_connected(&self, node_id: &PublicKey) -> BackendResult<bool>;
+
+    async fn fund_channel(&self, params: FundChannelParams) -> BackendResult<FundedChannel>;
+
+    /// Liquidity ad of `node_id` from gossip, `None` when it advertises none
+    async fn lease_rates(&self, node_id: &PublicKey) -> BackendResult<Option<LeaseRates>>;
+
+    /// Every channel the node knows of, with its current state
+    async fn list_channels(&self) -> BackendResult<Vec<ChannelInfo>>;
+
+    /// What the on-chain wallet can spend on funding transactions
+    async fn onchain_balance(&self) -> BackendResult<OnchainBalance>;
+
+    async fn create_invoice(&self, params: InvoiceParams) -> BackendResult<Invoice>;
+
+    async fn decode_invoice(&self, bolt11: &str) -> BackendResult<DecodedInvoice>;
+
+    async fn pay_invoice(&self, params: PayInvoiceParams) -> BackendResult<Payment>;
+
+    /// Latest payment of `payment_hash`: complete once an attempt completed, pending while
+    /// one is in flight, `None` when it was never paid
+    async fn payment_status(&self, payment_hash: &str) -> BackendResult<Option<Payment>>;
+
+    /// Verify a zbase32 `signmessage` signature (legacy LNURL-auth mode)
+    async fn check_message(&self, _message: &str, _zbase: &str, _pubkey: &PublicKey) -> BackendResult<bool> {
+        Err(BackendError::Unsupported("checkmessage"))
+    }
+}
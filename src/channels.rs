@@ -0,0 +1,41 @@
+//! Channels opened through LNURL-channel (LUD-02)
+//!
+//! Every funded channel is recorded against the k1 and the remote node that
+//! asked for it. A watcher polls the node and keeps the state of the channels
+//! up to date until they are closed, so the operator and the wallet can query
+//! how an opening went.
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::ChannelState;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpenedChannel {
+    pub k1: String,
+    pub remote_id: String, // node id of the wallet
+    pub channel_id: String,
+    pub txid: String, // funding transaction
+    pub outnum: u32, // funding output
+    pub capacity_sat: u64,
+    pub state: ChannelState,
+    pub short_channel_id: Option<String>, // once the funding transaction confirmed
+    pub opened_at: u64, // unix timestamp, in seconds
+    pub updated_at: u64, // last state change, unix timestamp, in seconds
+    pub lease: Option<ChannelLease>, // inbound liquidity leased from the wallet
+}
+
+impl OpenedChannel {
+    /// What the funding cost on-chain: the capacity and the lease fee
+    pub fn onchain_cost_sat(&self) -> u64 {
+        let lease_fee_sat = self.lease.as_ref().map_or(0, |lease| lease.fee_msat.div_ceil(1000));
+        self.capacity_sat + lease_fee_sat
+    }
+}
+
+/// Liquidity lease bought with a channel, on the rates the wallet advertised
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelLease {
+    pub request_sat: u64,
+    pub compact_lease: String, // rates accepted
+    pub fee_msat: u64, // lease fee at these rates, funding weight fees excluded
+}
@@ -1,86 +1,102 @@
-use cln_rpc::{
-    model::{requests as creq, responses as cresp},
-    ClnRpc,
-};
+use lnurl_project::backend::{ClnBackend, InvoiceParams, LightningBackend, PayInvoiceParams};
 use lnurl_project::*;
-use reqwest::Client;
+use reqwest::{Client, Url};
+use secp256k1::SecretKey;
 use std::error::Error;
 
 const SERVER_URL: &str = "http://127.0.0.1:3000"; // localhost pour tests// URL de ton serveur local pour test
 // const SERVER_URL: &str = "http://IP_DU_PROF:3000"; // Quand tu testes avec le serveur du prof
+const PAYOUT_POLL_ATTEMPTS: u32 = 60; // 2 minutes, checked every 2s
 
+/// Connect to our own Core Lightning node (the "wallet" side of the flows)
+async fn connect_node() -> Result<ClnBackend, Box<dyn Error>> {
+    let home = std::env::var("HOME").expect("HOME env var not set");
+    let rpc_path = format!("{home}/.lightning/testnet4/lightning-rpc");
+    Ok(ClnBackend::connect(&rpc_path).await?)
+}
 
-fn parse_uri(uri: &str) -> Result<(String, String, u16), Box<dyn Error>> {
-    // format "pubkey@ip:port"
-    let parts: Vec<_> = uri.split('@').collect();
-    if parts.len() != 2 {
-        return Err("invalid uri format".into());
-    }
+/// GET `url` and decode the JSON answer, surfacing the service's `reason` on `{"status":"ERROR"}`
+async fn get_json<T>(client: &Client, url: impl reqwest::IntoUrl) -> Result<T, Box<dyn Error>>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let body = client.get(url).send().await?.text().await?;
+    parse_lnurl_response(&body).map_err(|reason| format!("service error: {}", reason).into())
+}
 
-    let node_id = parts[0].to_string();
-    let socket = parts[1];
+// ============================================================================
+// LUD-01: LNURL entry point
+// ============================================================================
 
-    let addr_parts: Vec<_> = socket.split(':').collect();
-    if addr_parts.len() != 2 {
-        return Err("invalid address format".into());
+/// Ask the server for the LNURL of one of its endpoints (what a QR code would show)
+async fn fetch_lnurl(client: &Client, endpoint: &str) -> Result<String, Box<dyn Error>> {
+    let resp: LnurlResponse = get_json(client, format!("{}/lnurl/{}", SERVER_URL, endpoint)).await?;
+
+    Ok(resp.lnurl)
+}
+
+/// Decode a `lnurl1...` string and run the flow matching its tag
+async fn scan_lnurl(client: &Client, lnurl: &str) -> Result<(), Box<dyn Error>> {
+    let url = Url::parse(&decode_lnurl(lnurl)?)?;
+    println!("🔎 Decoded LNURL: {}", url);
+
+    // LUD-04: tag and k1 are carried by the URL itself
+    if url.query_pairs().any(|(k, v)| k == "tag" && v == AUTH_TAG) {
+        return test_lnurl_auth(client, url).await;
     }
 
-    let host = addr_parts[0].to_string();
-    let port: u16 = addr_parts[1].parse()?;
+    println!("📡 Calling {} ...", url);
+    let resp: serde_json::Value = get_json(client, url).await?;
 
-    Ok((node_id, host, port))
+    match resp["tag"].as_str() {
+        Some(CHANNEL_REQUEST_TAG) => test_channel_request(client, serde_json::from_value(resp)?).await,
+        Some(WITHDRAW_REQUEST_TAG) => test_withdraw_request(client, serde_json::from_value(resp)?).await,
+        Some(PAY_REQUEST_TAG) => test_pay_request(client, serde_json::from_value(resp)?).await,
+        Some(tag) => Err(format!("unsupported LNURL tag: {}", tag).into()),
+        None => Err(format!("unexpected LNURL response: {}", resp).into()),
+    }
 }
 
 // ============================================================================
 // LUD-02: Channel Request
 // ============================================================================
 
-async fn test_channel_request(client: &Client) -> Result<(), Box<dyn Error>> {
+async fn test_channel_request(client: &Client, req: ChannelRequestResponse) -> Result<(), Box<dyn Error>> {
     println!("\n🔷 Testing LUD-02: Channel Request");
     println!("=====================================");
 
-    // 1. Get channel request info
-    println!("📡 Calling /channel-request ...");
-    let req: ChannelRequestResponse = client
-        .get(&format!("{}/channel-request", SERVER_URL))
-        .send()
-        .await?
-        .json()
-        .await?;
-
+    // 1. Channel request info (fetched through the LNURL)
     println!("✅ Received channel request:");
     println!("   tag: {}", req.tag);
     println!("   k1: {}", req.k1);
     println!("   callback: {}", req.callback);
     println!("   uri: {}", req.uri);
+    if let Some(lease) = &req.lease {
+        println!("   lease: {} sat of inbound liquidity, up to {} msat in fees", lease.request_sat, lease.max_fee_msat);
+    }
 
     // 2. Parse URI
-    let (_node_id, _host, _port) = parse_uri(&req.uri)?;
+    let (node_id, host, port) = config::parse_node_uri(&req.uri)?;
 
     // 3. Get our node ID (from Core Lightning)
-    let home = std::env::var("HOME").expect("HOME env var not set");
-    let rpc_path = format!("{home}/.lightning/testnet4/lightning-rpc");
-    let mut cln = ClnRpc::new(&rpc_path).await?;
-
-    let getinfo_req = creq::GetinfoRequest {};
-    let info: cresp::GetinfoResponse = cln.call_typed(&getinfo_req).await?;
+    let node = connect_node().await?;
+    let info = node.get_info().await?;
     let our_pubkey = hex::encode(info.id.serialize());
 
     println!("📍 Our node pubkey: {}", our_pubkey);
 
-    // 4. Call the callback to open channel
+    // 4. Connect to the service node, it only funds channels to connected peers
+    println!("🔌 Connecting to {}@{}:{} ...", node_id, host, port);
+    node.connect_peer(&node_id, Some(&host), Some(port)).await?;
+
+    // 5. Call the callback to open channel
     println!("📡 Calling callback to open channel...");
     let callback_url = format!(
         "{}?k1={}&remoteid={}&private=0",
         req.callback, req.k1, our_pubkey
     );
 
-    let resp: OpenChannelResponse = client
-        .get(&callback_url)
-        .send()
-        .await?
-        .json()
-        .await?;
+    let resp: OpenChannelResponse = get_json(client, &callback_url).await?;
 
     println!("✅ Channel request response: {}", resp.status);
     println!("🎉 Channel request test completed!\n");
@@ -88,23 +104,28 @@ async fn test_channel_request(client: &Client) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Abandon a channel request (LUD-02 `cancel=1`), the service forgets its k1
+async fn cancel_channel_request(client: &Client, req: ChannelRequestResponse) -> Result<(), Box<dyn Error>> {
+    let node = connect_node().await?;
+    let our_pubkey = hex::encode(node.get_info().await?.id.serialize());
+
+    println!("📡 Cancelling channel request {}...", req.k1);
+    let callback_url = format!("{}?k1={}&remoteid={}&cancel=1", req.callback, req.k1, our_pubkey);
+
+    let resp: OpenChannelResponse = get_json(client, &callback_url).await?;
+    println!("✅ Channel request cancelled: {}", resp.status);
+    Ok(())
+}
+
 // ============================================================================
 // LUD-03: Withdraw Request
 // ============================================================================
 
-async fn test_withdraw_request(client: &Client) -> Result<(), Box<dyn Error>> {
+async fn test_withdraw_request(client: &Client, req: WithdrawRequestResponse) -> Result<(), Box<dyn Error>> {
     println!("\n💰 Testing LUD-03: Withdraw Request");
     println!("====================================");
 
-    // 1. Get withdraw request info
-    println!("📡 Calling /withdraw-request ...");
-    let req: WithdrawRequestResponse = client
-        .get(&format!("{}/withdraw-request", SERVER_URL))
-        .send()
-        .await?
-        .json()
-        .await?;
-
+    // 1. Withdraw request info (fetched through the LNURL)
     println!("✅ Received withdraw request:");
     println!("   tag: {}", req.tag);
     println!("   k1: {}", req.k1);
@@ -113,26 +134,18 @@ async fn test_withdraw_request(client: &Client) -> Result<(), Box<dyn Error>> {
     println!("   max: {} msats", req.max_withdrawable);
 
     // 2. Create an invoice (using Core Lightning)
-    let home = std::env::var("HOME").expect("HOME env var not set");
-    let rpc_path = format!("{home}/.lightning/testnet4/lightning-rpc");
-    let mut cln = ClnRpc::new(&rpc_path).await?;
+    let node = connect_node().await?;
 
-    let amount_msats = 50_000; // 50 sats
-    let invoice_req = creq::InvoiceRequest {
-        amount_msat: cln_rpc::primitives::AmountOrAny::Amount(
-            cln_rpc::primitives::Amount::from_msat(amount_msats)
-        ),
+    let amount_msats = 50_000u64.clamp(req.min_withdrawable, req.max_withdrawable); // 50 sats, within the advertised bounds
+    let invoice_req = InvoiceParams {
+        amount_msat: amount_msats,
         description: "LNURL withdraw test".to_string(),
         label: format!("lnurl-withdraw-{}", chrono::Utc::now().timestamp()),
         expiry: Some(3600),
-        fallbacks: None,
-        preimage: None,
-        cltv: None,
-        deschashonly: None,
-        exposeprivatechannels: None,
+        description_hash_only: false,
     };
 
-    let invoice_resp: cresp::InvoiceResponse = cln.call_typed(&invoice_req).await?;
+    let invoice_resp = node.create_invoice(invoice_req).await?;
     let bolt11 = invoice_resp.bolt11;
 
     println!("📄 Generated invoice: {}...", &bolt11[..50]);
@@ -141,80 +154,200 @@ async fn test_withdraw_request(client: &Client) -> Result<(), Box<dyn Error>> {
     println!("📡 Calling callback to withdraw...");
     let callback_url = format!("{}?k1={}&pr={}", req.callback, req.k1, bolt11);
 
-    let resp: WithdrawResponse = client
-        .get(&callback_url)
-        .send()
-        .await?
-        .json()
-        .await?;
+    let resp: WithdrawResponse = get_json(client, &callback_url).await?;
 
     println!("✅ Withdraw response: {}", resp.status);
-    println!("🎉 Withdraw request test completed!\n");
 
-    Ok(())
+    // 4. The service pays in the background, follow the payout until it ends
+    // The withdraw request may come from any LNURL service, ask the one that issued the callback
+    let status_url = Url::parse(&req.callback)?.join(&format!("/withdraw-status/{}", req.k1))?;
+    for _ in 0..PAYOUT_POLL_ATTEMPTS {
+        let status: WithdrawStatusResponse = get_json(client, status_url.clone()).await?;
+        match status.status.as_str() {
+            "succeeded" => {
+                println!("✅ Paid: {}", status.payment_hash.unwrap_or_default());
+                println!("🎉 Withdraw request test completed!\n");
+                return Ok(());
+            }
+            "failed" => return Err(format!("payout failed: {}", status.reason.unwrap_or_default()).into()),
+            _ => {
+                println!("⏳ Payout {} (attempt {})...", status.status, status.attempts);
+                tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+            }
+        }
+    }
+
+    Err(format!("payout still pending after {} checks, see {}", PAYOUT_POLL_ATTEMPTS, status_url).into())
 }
 
 // ============================================================================
 // LUD-04: LNURL-auth
 // ============================================================================
 
-async fn test_lnurl_auth(client: &Client) -> Result<(), Box<dyn Error>> {
+async fn test_lnurl_auth(client: &Client, url: Url) -> Result<(), Box<dyn Error>> {
     println!("\n🔐 Testing LUD-04: LNURL-auth");
     println!("==============================");
 
-    // 1. Get auth challenge
-    println!("📡 Calling /auth-challenge ...");
-    let challenge: AuthChallengeResponse = client
-        .get(&format!("{}/auth-challenge", SERVER_URL))
-        .send()
-        .await?
-        .json()
-        .await?;
+    // 1. Read the challenge from the LNURL query string
+    let query = |key: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.into_owned())
+    };
+    let k1 = query("k1").ok_or("LNURL-auth URL has no k1")?;
 
     println!("✅ Received auth challenge:");
-    println!("   tag: {}", challenge.tag);
-    println!("   k1: {}", challenge.k1);
-    println!("   action: {:?}", challenge.action);
+    println!("   k1: {}", k1);
+    println!("   action: {:?}", query("action"));
 
-    // 2. Sign the challenge using Core Lightning
-    let home = std::env::var("HOME").expect("HOME env var not set");
-    let rpc_path = format!("{home}/.lightning/testnet4/lightning-rpc");
-    let mut cln = ClnRpc::new(&rpc_path).await?;
+    // 2. Sign the raw k1 with a linking key (DER-encoded ECDSA, LUD-04)
+    let linking_key = SecretKey::from_slice(&rand::random::<[u8; 32]>())?;
+    let (signature, pubkey) = sign_auth_challenge(&k1, &linking_key)?;
 
-    // Sign with signmessage
-    let sign_req = creq::SignmessageRequest {
-        message: challenge.k1.clone(),
-    };
+    println!("📝 Signature: {}", signature);
+    println!("🔑 Linking key: {}", pubkey);
+
+    // 3. Call the LNURL back with sig and key appended
+    let mut auth_url = url.clone();
+    auth_url
+        .query_pairs_mut()
+        .append_pair("sig", &signature)
+        .append_pair("key", &pubkey);
+    println!("📡 Calling {} ...", auth_url.path());
 
-    let sign_resp: cresp::SignmessageResponse = cln.call_typed(&sign_req).await?;
-    let signature = sign_resp.zbase;
+    let resp: AuthResponse = get_json(client, auth_url).await?;
 
-    // Get our node pubkey
-    let getinfo_req = creq::GetinfoRequest {};
-    let info: cresp::GetinfoResponse = cln.call_typed(&getinfo_req).await?;
-    let pubkey = hex::encode(info.id.serialize());
+    println!("✅ Auth response: {}", resp.status);
+    println!("   event: {:?}", resp.event);
+    println!("🎉 LNURL-auth test completed!\n");
 
-    println!("📝 Signature: {}...", &signature[..50]);
-    println!("🔑 Pubkey: {}", pubkey);
+    Ok(())
+}
 
-    // 3. Call auth-response with signature
-    println!("📡 Calling /auth-response ...");
-    let auth_url = format!(
-        "{}/auth-response?k1={}&sig={}&key={}",
-        SERVER_URL, challenge.k1, signature, pubkey
-    );
+// ============================================================================
+// LUD-06: Pay Request
+// ============================================================================
+
+async fn test_pay_request(client: &Client, req: PayRequestResponse) -> Result<(), Box<dyn Error>> {
+    println!("\n💸 Testing LUD-06: Pay Request");
+    println!("==============================");
+
+    // 1. Pay request info (fetched through the LNURL)
+    println!("✅ Received pay request:");
+    println!("   tag: {}", req.tag);
+    println!("   callback: {}", req.callback);
+    println!("   min: {} msats", req.min_sendable);
+    println!("   max: {} msats", req.max_sendable);
+    println!("   metadata: {}", req.metadata);
+
+    let metadata: Vec<(String, serde_json::Value)> = serde_json::from_str(&req.metadata)
+        .map_err(|e| format!("invalid metadata: {}", e))?;
+    if let Some((_, identifier)) = metadata.iter().find(|(mime, _)| mime == "text/identifier") {
+        println!("   identifier: {}", identifier);
+    }
 
-    let resp: AuthResponse = client
-        .get(&auth_url)
+    // 2. Ask the service for an invoice
+    let amount_msats = 10_000u64.clamp(req.min_sendable, req.max_sendable); // 10 sats
+    let mut callback_url = Url::parse(&req.callback)?;
+    callback_url
+        .query_pairs_mut()
+        .append_pair("amount", &amount_msats.to_string());
+
+    println!("📡 Calling callback for {} msats...", amount_msats);
+    let resp: PayCallbackResponse = get_json(client, callback_url).await?;
+
+    println!("📄 Received invoice: {}...", &resp.pr[..50.min(resp.pr.len())]);
+
+    // 3. Check the invoice commits to the metadata and the requested amount
+    let node = connect_node().await?;
+    let decoded = node.decode_invoice(&resp.pr).await?;
+
+    if decoded.description_hash != Some(metadata_hash(&req.metadata)) {
+        return Err("invoice description hash does not match metadata".into());
+    }
+    if decoded.amount_msat != Some(amount_msats) {
+        return Err("invoice amount does not match the requested amount".into());
+    }
+    println!("🔍 Description hash and amount verified");
+
+    // 4. Pay the invoice
+    println!("📡 Paying invoice...");
+    let paid = node.pay_invoice(PayInvoiceParams {
+        bolt11: resp.pr,
+        max_fee_msat: None,
+        retry_for_secs: None,
+        max_delay: None,
+        exclude: Vec::new(),
+    }).await?;
+
+    println!("✅ Payment status: {:?}", paid.status);
+    println!("🎉 Pay request test completed!\n");
+
+    Ok(())
+}
+
+// ============================================================================
+// LUD-16: Lightning Address
+// ============================================================================
+
+/// Resolve `user@domain` and run the pay flow against it
+async fn pay_lightning_address(client: &Client, address: &str) -> Result<(), Box<dyn Error>> {
+    let url = lightning_address_url(address)?;
+    println!("📡 Resolving {} via {} ...", address, url);
+
+    let req: PayRequestResponse = get_json(client, url).await?;
+    if req.tag != PAY_REQUEST_TAG {
+        return Err(format!("unexpected tag for a Lightning Address: {}", req.tag).into());
+    }
+
+    // The service must commit to the address we are paying
+    let metadata: Vec<(String, serde_json::Value)> = serde_json::from_str(&req.metadata)?;
+    let identified = metadata.iter().any(|(mime, value)| {
+        (mime == "text/identifier" || mime == "text/email")
+            && value.as_str().is_some_and(|v| v.eq_ignore_ascii_case(address.trim()))
+    });
+    if !identified {
+        return Err("payRequest metadata does not contain the Lightning Address".into());
+    }
+
+    test_pay_request(client, req).await
+}
+
+// ============================================================================
+// Internal API (backoffice)
+// ============================================================================
+
+/// Have the server open a channel to `uri` (pubkey@host:port) through POST /internal/open-channel
+/// The API token is read from LNURL_INTERNAL_API_TOKEN, like the server does
+async fn internal_open_channel(client: &Client, uri: &str, satoshis: u64) -> Result<(), Box<dyn Error>> {
+    let token = std::env::var("LNURL_INTERNAL_API_TOKEN").map_err(|_| "LNURL_INTERNAL_API_TOKEN is not set")?;
+    let (node_id, host, port) = config::parse_node_uri(uri)?;
+
+    println!("📡 Asking the server to open {} sat to {}...", satoshis, uri);
+    let req = InternalOpenChannelRequest {
+        node_id: node_id.to_string(),
+        host,
+        port,
+        satoshis,
+        private: false,
+        push_msat: None,
+        skip_node_limits: false,
+    };
+    let body = client
+        .post(format!("{}/internal/open-channel", SERVER_URL))
+        .bearer_auth(token)
+        .json(&req)
         .send()
         .await?
-        .json()
+        .text()
         .await?;
 
-    println!("✅ Auth response: {}", resp.status);
-    println!("   event: {:?}", resp.event);
-    println!("🎉 LNURL-auth test completed!\n");
-
+    let resp: InternalOpenChannelResponse =
+        parse_lnurl_response(&body).map_err(|reason| format!("service error: {}", reason))?;
+    println!("✅ Channel funded:");
+    println!("   channel_id: {}", resp.channel_id);
+    println!("   txid: {}:{}", resp.txid, resp.outnum);
+    println!("   status: /channel-status/{}", resp.k1);
     Ok(())
 }
 
@@ -222,21 +355,83 @@ async fn test_lnurl_auth(client: &Client) -> Result<(), Box<dyn Error>> {
 // MAIN - Menu interactif
 // ============================================================================
 
+/// Fetch the LNURL-auth QR content from /auth-challenge, with the secret the page waits with
+async fn fetch_auth_challenge(client: &Client) -> Result<(String, AuthChallengeResponse), Box<dyn Error>> {
+    let challenge: AuthChallengeResponse = get_json(client, format!("{}/auth-challenge", SERVER_URL)).await?;
+
+    let lnurl = challenge.lnurl.clone().ok_or("server did not return an LNURL")?;
+    Ok((lnurl, challenge))
+}
+
+async fn run_channel_request(client: &Client) -> Result<(), Box<dyn Error>> {
+    let lnurl = fetch_lnurl(client, "channel-request").await?;
+    scan_lnurl(client, &lnurl).await
+}
+
+async fn run_cancel_channel_request(client: &Client) -> Result<(), Box<dyn Error>> {
+    let lnurl = fetch_lnurl(client, "channel-request").await?;
+    let url = decode_lnurl(&lnurl)?;
+    let req: ChannelRequestResponse = get_json(client, url).await?;
+    cancel_channel_request(client, req).await
+}
+
+async fn run_withdraw_request(client: &Client) -> Result<(), Box<dyn Error>> {
+    let lnurl = fetch_lnurl(client, "withdraw-request").await?;
+    scan_lnurl(client, &lnurl).await
+}
+
+async fn run_pay_request(client: &Client) -> Result<(), Box<dyn Error>> {
+    let lnurl = fetch_lnurl(client, "pay-request").await?;
+    scan_lnurl(client, &lnurl).await
+}
+
+async fn run_lnurl_auth(client: &Client) -> Result<(), Box<dyn Error>> {
+    let (lnurl, challenge) = fetch_auth_challenge(client).await?;
+    scan_lnurl(client, &lnurl).await?;
+
+    // What the login page gets once the wallet signed
+    let Some(session) = challenge.session else {
+        return Ok(());
+    };
+    let mut url = Url::parse(&format!("{}/auth-session", SERVER_URL))?;
+    url.query_pairs_mut().append_pair("k1", &challenge.k1).append_pair("session", &session);
+    let resp: AuthSessionResponse = get_json(client, url).await?;
+    println!("🪪 Session: {}", resp.state);
+    if let (Some(token), Some(expires_at)) = (resp.token, resp.expires_at) {
+        println!("   Token: {}", token);
+        println!("   Expires at: {}", expires_at);
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let client = Client::new();
 
+    // `client lnurl1...` or `client user@domain` runs that flow and exits
+    if let Some(arg) = std::env::args().nth(1) {
+        if arg.contains('@') {
+            return pay_lightning_address(&client, &arg).await;
+        }
+        return scan_lnurl(&client, &arg).await;
+    }
+
     println!("\n⚡ LNURL Client - Test Suite");
     println!("============================\n");
     println!("What would you like to test?");
     println!("1. LUD-02: Channel Request");
     println!("2. LUD-03: Withdraw Request");
     println!("3. LUD-04: LNURL-auth");
-    println!("4. Run all tests");
+    println!("4. LUD-06: Pay Request");
+    println!("5. LUD-16: Pay a Lightning Address");
+    println!("6. Run all tests");
+    println!("7. Scan an LNURL (lnurl1...)");
+    println!("8. LUD-02: Cancel a Channel Request");
+    println!("9. Internal: have the server open a channel");
     println!("0. Exit");
 
     loop {
-        print!("\nEnter your choice (0-4): ");
+        print!("\nEnter your choice (0-9): ");
         use std::io::{self, Write};
         io::stdout().flush()?;
 
@@ -246,33 +441,81 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
         match choice {
             "1" => {
-                if let Err(e) = test_channel_request(&client).await {
+                if let Err(e) = run_channel_request(&client).await {
                     eprintln!("❌ Error: {}", e);
                 }
             }
             "2" => {
-                if let Err(e) = test_withdraw_request(&client).await {
+                if let Err(e) = run_withdraw_request(&client).await {
                     eprintln!("❌ Error: {}", e);
                 }
             }
             "3" => {
-                if let Err(e) = test_lnurl_auth(&client).await {
+                if let Err(e) = run_lnurl_auth(&client).await {
                     eprintln!("❌ Error: {}", e);
                 }
             }
             "4" => {
+                if let Err(e) = run_pay_request(&client).await {
+                    eprintln!("❌ Error: {}", e);
+                }
+            }
+            "5" => {
+                print!("Lightning Address (user@domain): ");
+                io::stdout().flush()?;
+                let mut address = String::new();
+                io::stdin().read_line(&mut address)?;
+                if let Err(e) = pay_lightning_address(&client, address.trim()).await {
+                    eprintln!("❌ Error: {}", e);
+                }
+            }
+            "6" => {
                 println!("\n🚀 Running all tests...\n");
-                if let Err(e) = test_channel_request(&client).await {
+                if let Err(e) = run_channel_request(&client).await {
                     eprintln!("❌ Channel request error: {}", e);
                 }
-                if let Err(e) = test_withdraw_request(&client).await {
+                if let Err(e) = run_withdraw_request(&client).await {
                     eprintln!("❌ Withdraw request error: {}", e);
                 }
-                if let Err(e) = test_lnurl_auth(&client).await {
+                if let Err(e) = run_lnurl_auth(&client).await {
                     eprintln!("❌ LNURL-auth error: {}", e);
                 }
+                if let Err(e) = run_pay_request(&client).await {
+                    eprintln!("❌ Pay request error: {}", e);
+                }
                 println!("✅ All tests completed!");
             }
+            "7" => {
+                print!("LNURL: ");
+                io::stdout().flush()?;
+                let mut lnurl = String::new();
+                io::stdin().read_line(&mut lnurl)?;
+                if let Err(e) = scan_lnurl(&client, lnurl.trim()).await {
+                    eprintln!("❌ Error: {}", e);
+                }
+            }
+            "8" => {
+                if let Err(e) = run_cancel_channel_request(&client).await {
+                    eprintln!("❌ Error: {}", e);
+                }
+            }
+            "9" => {
+                print!("Node URI (pubkey@host:port): ");
+                io::stdout().flush()?;
+                let mut uri = String::new();
+                io::stdin().read_line(&mut uri)?;
+                print!("Amount (sat): ");
+                io::stdout().flush()?;
+                let mut amount = String::new();
+                io::stdin().read_line(&mut amount)?;
+                let result = match amount.trim().parse() {
+                    Ok(satoshis) => internal_open_channel(&client, uri.trim(), satoshis).await,
+                    Err(e) => Err(format!("invalid amount: {}", e).into()),
+                };
+                if let Err(e) = result {
+                    eprintln!("❌ Error: {}", e);
+                }
+            }
             "0" => {
                 println!("👋 Goodbye!");
                 break;
@@ -0,0 +1,594 @@
+//! Server configuration: TOML file, overridden by CLI flags and environment
+//!
+//! Every section has defaults, so an empty file is valid as long as the
+//! required public URL comes from a flag or the environment.
+
+use crate::backend::{AddressKind, ChannelType};
+use crate::node_uri::DEFAULT_ADVERTISE;
+use reqwest::Url;
+use secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const NETWORKS: &[&str] = &["bitcoin", "testnet", "testnet4", "signet", "regtest"];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Missing(&'static str),
+    Invalid(&'static str, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "invalid {}: {}", path.display(), e),
+            ConfigError::Missing(key) => write!(f, "{} is required", key),
+            ConfigError::Invalid(key, reason) => write!(f, "invalid {}: {}", key, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerSection,
+    pub node: NodeSection,
+    pub auth: AuthSection,
+    pub internal: InternalSection,
+    pub k1: K1Settings,
+    pub storage: StorageSection,
+    pub channel: ChannelOffer, // served by /channel-request
+    pub channel_offers: BTreeMap<String, ChannelOffer>, // served by /channel-request/{name}
+    pub channel_policy: ChannelPolicy,
+    pub withdraw: WithdrawLimits,
+    pub payout: PayoutSettings,
+    pub pay: PayLimits,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    pub bind: String,
+    pub public_url: Option<String>, // base URL wallets reach us at, callbacks are built from it
+    pub ln_address_domain: Option<String>, // defaults to the host of public_url
+    pub users_file: PathBuf,
+}
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        ServerSection {
+            bind: "0.0.0.0:3000".to_string(),
+            public_url: None,
+            ln_address_domain: None,
+            users_file: PathBuf::from("users.json"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeSection {
+    pub network: String,
+    pub rpc_path: Option<PathBuf>, // defaults to ~/.lightning/<network>/lightning-rpc
+    pub uri: Option<String>, // pubkey@host:port, derived from getinfo when unset
+    pub advertise: Vec<AddressKind>, // announced address kinds to advertise, by preference
+    pub refresh_secs: u64, // how often getinfo is polled for a new id or address, 0 disables
+    pub channel_poll_secs: u64, // how often the state of LNURL channels is polled, 0 disables
+}
+
+impl Default for NodeSection {
+    fn default() -> Self {
+        NodeSection {
+            network: "testnet4".to_string(),
+            rpc_path: None,
+            uri: None,
+            advertise: DEFAULT_ADVERTISE.to_vec(),
+            refresh_secs: 600,
+            channel_poll_secs: 60,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSection {
+    pub legacy_zbase: bool, // accept Core Lightning zbase32 signatures when sig is not DER
+    pub session_secret: Option<String>, // signs session tokens, generated and kept by the storage when unset
+    pub session_ttl_secs: u64, // how long a login lasts
+}
+
+impl Default for AuthSection {
+    fn default() -> Self {
+        AuthSection {
+            legacy_zbase: false,
+            session_secret: None,
+            session_ttl_secs: 86_400,
+        }
+    }
+}
+
+/// Backoffice API under /internal, disabled without a token
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InternalSection {
+    pub api_token: Option<String>, // sent as `Authorization: Bearer <token>`
+}
+
+/// Shortest internal API token or session secret accepted, it must not be guessable
+pub const MIN_API_TOKEN_LEN: usize = 32;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct K1Settings {
+    pub max_entries: usize, // oldest k1s are evicted beyond this
+    pub sweep_secs: u64, // how often expired k1s are dropped
+    pub channel_ttl_secs: u64,
+    pub withdraw_ttl_secs: u64,
+    pub auth_ttl_secs: u64,
+}
+
+impl Default for K1Settings {
+    fn default() -> Self {
+        K1Settings {
+            max_entries: 10_000,
+            sweep_secs: 60,
+            channel_ttl_secs: 3600,
+            withdraw_ttl_secs: 3600,
+            auth_ttl_secs: 300,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    Memory, // lost on restart
+    Sqlite,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSection {
+    pub backend: StorageKind,
+    pub path: PathBuf, // SQLite database file
+}
+
+impl Default for StorageSection {
+    fn default() -> Self {
+        StorageSection {
+            backend: StorageKind::Sqlite,
+            path: PathBuf::from("lnurl.db"),
+        }
+    }
+}
+
+/// Size of the channels of an offer, in satoshis
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Capacity {
+    Fixed(u64),
+    Range { min: u64, max: u64 }, // the wallet picks with `capacity=`, the minimum otherwise
+}
+
+impl Capacity {
+    pub fn bounds(&self) -> (u64, u64) {
+        match *self {
+            Capacity::Fixed(amount) => (amount, amount),
+            Capacity::Range { min, max } => (min, max),
+        }
+    }
+
+    /// Capacity of a channel the wallet asked `requested` satoshis for
+    pub fn pick(&self, requested: Option<u64>) -> Result<u64, String> {
+        let (min, max) = self.bounds();
+        match requested {
+            None => Ok(min),
+            Some(amount) if (min..=max).contains(&amount) => Ok(amount),
+            Some(_) => Err(format!("capacity must be between {} and {} sat", min, max)),
+        }
+    }
+}
+
+/// What a LNURL-channel QR opens, every k1 carries the offer it was issued for
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelOffer {
+    #[serde(alias = "amount_sat")]
+    pub capacity: Capacity,
+    pub push_msat: u64, // given to the wallet at opening
+    pub feerate: Option<String>, // slow, normal, urgent, <n>perkw or <n>perkb
+    pub channel_type: ChannelType,
+    pub minconf: Option<u32>, // confirmations of the funding utxos
+    pub reserve_sat: Option<u64>, // reserve the wallet must keep
+    pub allow_private: bool, // honour private=1
+    pub lease: Option<LeaseTerms>, // inbound liquidity bought from the wallet
+}
+
+/// Inbound liquidity a channel offer leases from the wallet's liquidity ad (dual-funding),
+/// the wallet is told these terms in the channel request
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LeaseTerms {
+    pub request_sat: u64, // inbound liquidity asked from the wallet
+    pub max_fee_msat: u64, // highest lease fee paid for it, funding weight fees excluded
+}
+
+impl Default for ChannelOffer {
+    fn default() -> Self {
+        ChannelOffer {
+            capacity: Capacity::Fixed(100_000),
+            push_msat: 0,
+            feerate: None,
+            channel_type: ChannelType::Default,
+            minconf: None,
+            reserve_sat: None,
+            allow_private: true,
+            lease: None,
+        }
+    }
+}
+
+impl ChannelOffer {
+    pub fn validate(&self) -> Result<(), String> {
+        let (min, max) = self.capacity.bounds();
+        if min == 0 || min > max {
+            return Err("expected 0 < capacity, and min <= max for a range".to_string());
+        }
+        if self.push_msat >= min.saturating_mul(1000) {
+            return Err("push_msat must be below the capacity".to_string());
+        }
+        if let Some(feerate) = &self.feerate {
+            let number = feerate.strip_suffix("perkw").or_else(|| feerate.strip_suffix("perkb"));
+            let valid = matches!(feerate.as_str(), "slow" | "normal" | "urgent")
+                || number.is_some_and(|n| n.parse::<u32>().is_ok());
+            if !valid {
+                return Err(format!("{} is not slow, normal, urgent, <n>perkw or <n>perkb", feerate));
+            }
+        }
+        if self.reserve_sat.is_some_and(|reserve| reserve >= min) {
+            return Err("reserve_sat must be below the capacity".to_string());
+        }
+        if self.lease.as_ref().is_some_and(|lease| lease.request_sat == 0) {
+            return Err("lease.request_sat must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Longest wait of a channel callback for the wallet to connect
+pub const MAX_CONNECT_GRACE_SECS: u64 = 30;
+
+/// Checks made before funding a LNURL-channel, whatever its offer
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelPolicy {
+    pub connect_grace_secs: u64, // how long the wallet has to connect to our uri once it called back
+    pub outbound_connect: bool, // connect to the host= (and port=) the wallet gives when it is not connected
+    pub max_channels_per_node: u32, // channels not closed yet, 0 for no limit
+    pub min_open_interval_secs: u64, // between two channels to the same node, 0 for none
+    pub allow: Vec<String>, // node ids, when set only these get channels
+    pub deny: Vec<String>, // node ids that never get a channel
+    pub daily_budget_sat: Option<u64>, // capacity and lease fees funded over the last 24 hours
+    pub onchain_reserve_sat: u64, // confirmed funds a funding must leave in the wallet
+}
+
+impl Default for ChannelPolicy {
+    fn default() -> Self {
+        ChannelPolicy {
+            connect_grace_secs: 10,
+            outbound_connect: false,
+            max_channels_per_node: 0,
+            min_open_interval_secs: 0,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            daily_budget_sat: None,
+            onchain_reserve_sat: 0,
+        }
+    }
+}
+
+impl ChannelPolicy {
+    /// Why `node_id` may not get a channel at all, whatever it already has
+    pub fn refuses(&self, node_id: &PublicKey) -> Option<&'static str> {
+        let listed = |list: &[String]| list.iter().any(|entry| PublicKey::from_str(entry).ok() == Some(*node_id));
+        if listed(&self.deny) {
+            Some("this node is denied channels")
+        } else if !self.allow.is_empty() && !listed(&self.allow) {
+            Some("this node is not on the list of nodes we open channels to")
+        } else {
+            None
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        // The callback request waits that long, keep it short
+        if self.connect_grace_secs > MAX_CONNECT_GRACE_SECS {
+            return Err(format!("connect_grace_secs must be at most {}", MAX_CONNECT_GRACE_SECS));
+        }
+        for entry in self.allow.iter().chain(&self.deny) {
+            if PublicKey::from_str(entry).is_err() {
+                return Err(format!("{} is not a node id", entry));
+            }
+        }
+        if self.daily_budget_sat == Some(0) {
+            return Err("daily_budget_sat must be positive, leave it out for no budget".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WithdrawLimits {
+    pub open: bool, // serve /withdraw-request to anyone, links are served either way
+    pub min_withdrawable: u64, // in millisatoshis
+    pub max_withdrawable: u64, // in millisatoshis
+    pub default_description: String,
+    pub links_file: PathBuf, // withdraw links registry, see `crate::withdraw_links`
+}
+
+impl Default for WithdrawLimits {
+    fn default() -> Self {
+        WithdrawLimits {
+            open: false,
+            min_withdrawable: 1_000,
+            max_withdrawable: 1_000_000,
+            default_description: "LNURL withdraw".to_string(),
+            links_file: PathBuf::from("withdraw_links.json"),
+        }
+    }
+}
+
+/// Background payment of withdraw invoices
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PayoutSettings {
+    pub concurrency: usize, // payouts paid at the same time
+    pub max_attempts: u32, // transient failures are retried until then
+    pub retry_secs: u64, // wait before the n-th retry is n times this
+    pub pending_poll_secs: u64, // how often a payment in flight is looked up until it settles
+    pub pending_wait_secs: u64, // how long an attempt follows it before putting it back in the queue
+    pub fees: FeePolicy, // withdraw links can override it
+}
+
+impl Default for PayoutSettings {
+    fn default() -> Self {
+        PayoutSettings {
+            concurrency: 4,
+            max_attempts: 5,
+            retry_secs: 30,
+            pending_poll_secs: 10,
+            pending_wait_secs: 60,
+            fees: FeePolicy {
+                max_fee_percent: Some(1.0),
+                exempt_fee_msat: Some(5_000),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Routing limits of a payment, unset values are left to the node
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeePolicy {
+    pub max_fee_msat: Option<u64>,
+    pub max_fee_percent: Option<f64>, // of the amount, the lowest cap wins
+    pub exempt_fee_msat: Option<u64>, // the percentage never caps fees below this, small amounts stay payable
+    pub retry_for_secs: Option<u16>, // how long the node keeps trying routes
+    pub max_delay: Option<u16>, // CLTV delay allowed, in blocks
+    pub exclude: Vec<String>, // short channel ids (scid/direction) or node ids to route around
+}
+
+impl FeePolicy {
+    /// This policy with the values set in `over` taking precedence, exclusions add up
+    pub fn merged(&self, over: &FeePolicy) -> FeePolicy {
+        let mut exclude = self.exclude.clone();
+        exclude.extend(over.exclude.iter().filter(|e| !self.exclude.contains(e)).cloned());
+
+        FeePolicy {
+            max_fee_msat: over.max_fee_msat.or(self.max_fee_msat),
+            max_fee_percent: over.max_fee_percent.or(self.max_fee_percent),
+            exempt_fee_msat: over.exempt_fee_msat.or(self.exempt_fee_msat),
+            retry_for_secs: over.retry_for_secs.or(self.retry_for_secs),
+            max_delay: over.max_delay.or(self.max_delay),
+            exclude,
+        }
+    }
+
+    /// Routing fees allowed to pay `amount_msat`, `None` when uncapped
+    pub fn max_fee_for(&self, amount_msat: u64) -> Option<u64> {
+        let proportional = self
+            .max_fee_percent
+            .map(|percent| (amount_msat as f64 * percent / 100.0).floor() as u64)
+            .map(|fee| fee.max(self.exempt_fee_msat.unwrap_or(0)));
+
+        match (self.max_fee_msat, proportional) {
+            (Some(absolute), Some(proportional)) => Some(absolute.min(proportional)),
+            (absolute, proportional) => absolute.or(proportional),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_fee_percent.is_some_and(|percent| !(0.0..=100.0).contains(&percent)) {
+            return Err("max_fee_percent must be a percentage".to_string());
+        }
+        if self.retry_for_secs == Some(0) || self.max_delay == Some(0) {
+            return Err("retry_for_secs and max_delay must be positive".to_string());
+        }
+        for entry in &self.exclude {
+            if !is_route_exclusion(entry) {
+                return Err(format!("{} is neither a node id nor a short channel id/direction", entry));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `pay` excludes node ids and directed short channel ids such as `103x1x0/1`
+fn is_route_exclusion(entry: &str) -> bool {
+    if PublicKey::from_str(entry).is_ok() {
+        return true;
+    }
+    let Some((scid, direction)) = entry.split_once('/') else {
+        return false;
+    };
+    let parts: Vec<_> = scid.split('x').collect();
+    matches!(direction, "0" | "1") && parts.len() == 3 && parts.iter().all(|p| p.parse::<u64>().is_ok())
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PayLimits {
+    pub min_sendable: u64, // in millisatoshis
+    pub max_sendable: u64, // in millisatoshis
+    pub description: String,
+}
+
+impl Default for PayLimits {
+    fn default() -> Self {
+        PayLimits {
+            min_sendable: 1_000,
+            max_sendable: 1_000_000_000,
+            description: "LNURL pay".to_string(),
+        }
+    }
+}
+
+/// Split a `pubkey@host:port` node URI
+pub fn parse_node_uri(uri: &str) -> Result<(PublicKey, String, u16), String> {
+    let (id, address) = uri.split_once('@').ok_or("expected pubkey@host:port")?;
+    let id = PublicKey::from_str(id).map_err(|_| format!("{} is not a node public key", id))?;
+
+    // rsplit so that bracketed IPv6 addresses keep their colons
+    let (host, port) = address.rsplit_once(':').ok_or("expected pubkey@host:port")?;
+    let port = port.parse().map_err(|_| format!("{} is not a port", port))?;
+    if host.is_empty() {
+        return Err("host is empty".to_string());
+    }
+
+    Ok((id, host.to_string(), port))
+}
+
+impl Config {
+    /// Read a TOML config file, `Config::default()` when the file is absent and `required` is false
+    pub fn load(path: &Path, required: bool) -> Result<Self, ConfigError> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+                return Ok(Config::default())
+            }
+            Err(e) => return Err(ConfigError::Read(path.to_path_buf(), e)),
+        };
+
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Check every value and fill in the derived defaults
+    pub fn validate(mut self) -> Result<Self, ConfigError> {
+        self.server
+            .bind
+            .parse::<SocketAddr>()
+            .map_err(|e| ConfigError::Invalid("server.bind", e.to_string()))?;
+
+        let public_url = self.server.public_url.as_deref().ok_or(ConfigError::Missing("server.public_url"))?;
+        let url = Url::parse(public_url).map_err(|e| ConfigError::Invalid("server.public_url", e.to_string()))?;
+        if !matches!(url.scheme(), "http" | "https") || url.query().is_some() {
+            return Err(ConfigError::Invalid(
+                "server.public_url",
+                "expected an http(s) base URL without query".to_string(),
+            ));
+        }
+        self.server.public_url = Some(public_url.trim_end_matches('/').to_string());
+
+        if self.server.ln_address_domain.is_none() {
+            let host = url.host_str().unwrap_or_default();
+            self.server.ln_address_domain = Some(match url.port() {
+                Some(port) => format!("{}:{}", host, port),
+                None => host.to_string(),
+            });
+        }
+
+        if !NETWORKS.contains(&self.node.network.as_str()) {
+            return Err(ConfigError::Invalid(
+                "node.network",
+                format!("{} is not one of {}", self.node.network, NETWORKS.join(", ")),
+            ));
+        }
+
+        if self.node.rpc_path.is_none() {
+            let home = std::env::var("HOME").map_err(|_| ConfigError::Missing("node.rpc_path (HOME is not set)"))?;
+            let network_dir = self.node.network.as_str();
+            self.node.rpc_path = Some(PathBuf::from(format!("{home}/.lightning/{network_dir}/lightning-rpc")));
+        }
+
+        if let Some(uri) = &self.node.uri {
+            parse_node_uri(uri).map_err(|e| ConfigError::Invalid("node.uri", e))?;
+        }
+        if self.node.advertise.is_empty() {
+            return Err(ConfigError::Invalid("node.advertise", "must list at least one address kind".to_string()));
+        }
+
+        if self.internal.api_token.as_ref().is_some_and(|token| token.len() < MIN_API_TOKEN_LEN) {
+            return Err(ConfigError::Invalid(
+                "internal.api_token",
+                format!("must be at least {} characters long", MIN_API_TOKEN_LEN),
+            ));
+        }
+
+        if self.auth.session_secret.as_ref().is_some_and(|secret| secret.len() < MIN_API_TOKEN_LEN) {
+            return Err(ConfigError::Invalid(
+                "auth.session_secret",
+                format!("must be at least {} characters long", MIN_API_TOKEN_LEN),
+            ));
+        }
+        if self.auth.session_ttl_secs == 0 {
+            return Err(ConfigError::Invalid("auth", "session_ttl_secs must be positive".to_string()));
+        }
+
+        let k1 = &self.k1;
+        if k1.max_entries == 0 || k1.sweep_secs == 0 {
+            return Err(ConfigError::Invalid("k1", "max_entries and sweep_secs must be positive".to_string()));
+        }
+        if k1.channel_ttl_secs == 0 || k1.withdraw_ttl_secs == 0 || k1.auth_ttl_secs == 0 {
+            return Err(ConfigError::Invalid("k1", "TTLs must be positive".to_string()));
+        }
+
+        self.channel.validate().map_err(|e| ConfigError::Invalid("channel", e))?;
+        for (name, offer) in &self.channel_offers {
+            if !crate::is_valid_username(name) {
+                return Err(ConfigError::Invalid("channel_offers", format!("invalid offer name {:?}", name)));
+            }
+            offer.validate().map_err(|e| ConfigError::Invalid("channel_offers", format!("{}: {}", name, e)))?;
+        }
+        self.channel_policy.validate().map_err(|e| ConfigError::Invalid("channel_policy", e))?;
+        if self.withdraw.min_withdrawable == 0 || self.withdraw.min_withdrawable > self.withdraw.max_withdrawable {
+            return Err(ConfigError::Invalid(
+                "withdraw",
+                "expected 0 < min_withdrawable <= max_withdrawable".to_string(),
+            ));
+        }
+        let payout = &self.payout;
+        if payout.concurrency == 0 || payout.max_attempts == 0 {
+            return Err(ConfigError::Invalid("payout", "concurrency and max_attempts must be positive".to_string()));
+        }
+        payout.fees.validate().map_err(|e| ConfigError::Invalid("payout.fees", e))?;
+        if self.pay.min_sendable == 0 || self.pay.min_sendable > self.pay.max_sendable {
+            return Err(ConfigError::Invalid(
+                "pay",
+                "expected 0 < min_sendable <= max_sendable".to_string(),
+            ));
+        }
+
+        Ok(self)
+    }
+}
@@ -0,0 +1,176 @@
+//! Issued k1 challenges: what they were issued for and where they stand
+//!
+//! Every k1 remembers the flow it was issued for and the terms advertised
+//! with it, so a callback of another flow cannot consume it. The k1s
+//! themselves are kept by a `crate::storage::Storage`.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::config::ChannelOffer;
+
+/// Flow a k1 can be issued for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum K1Kind {
+    Channel,
+    Withdraw,
+    Auth,
+}
+
+impl fmt::Display for K1Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            K1Kind::Channel => write!(f, "channel"),
+            K1Kind::Withdraw => write!(f, "withdraw"),
+            K1Kind::Auth => write!(f, "login"),
+        }
+    }
+}
+
+/// What a k1 was issued for, with the terms advertised to the wallet at that time
+/// (pay requests carry no k1)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum K1Purpose {
+    Channel {
+        offer: ChannelOffer,
+    },
+    Withdraw {
+        min_withdrawable: u64, // in millisatoshis
+        max_withdrawable: u64, // in millisatoshis
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        link: Option<String>, // withdraw link spent from, none for the open withdraw request
+    },
+    Auth {
+        action: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session_hash: Option<String>, // sha256 of the secret the login page waits with
+    },
+}
+
+impl K1Purpose {
+    pub fn kind(&self) -> K1Kind {
+        match self {
+            K1Purpose::Channel { .. } => K1Kind::Channel,
+            K1Purpose::Withdraw { .. } => K1Kind::Withdraw,
+            K1Purpose::Auth { .. } => K1Kind::Auth,
+        }
+    }
+}
+
+/// Lifecycle of a k1: issued → in progress → succeeded, failed or cancelled
+/// A callback takes the k1 from issued to in progress, only one can hold it;
+/// a transient failure gives it back (issued) so the wallet can retry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum K1Status {
+    Issued,
+    InProgress,
+    Succeeded,
+    Failed,
+    Cancelled, // by the wallet (LUD-02 cancel=1)
+}
+
+impl K1Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            K1Status::Issued => "issued",
+            K1Status::InProgress => "in_progress",
+            K1Status::Succeeded => "succeeded",
+            K1Status::Failed => "failed",
+            K1Status::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl FromStr for K1Status {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "issued" => Ok(K1Status::Issued),
+            "in_progress" => Ok(K1Status::InProgress),
+            "succeeded" => Ok(K1Status::Succeeded),
+            "failed" => Ok(K1Status::Failed),
+            "cancelled" => Ok(K1Status::Cancelled),
+            _ => Err(format!("unknown k1 status {}", s)),
+        }
+    }
+}
+
+/// How the callback that consumed a k1 ended
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum K1Outcome {
+    Succeeded(String), // payment hash, funding txid or linking key
+    Failed(String), // reason given to the wallet
+    Cancelled(String), // node id of the wallet that cancelled
+}
+
+impl K1Outcome {
+    pub fn status(&self) -> K1Status {
+        match self {
+            K1Outcome::Succeeded(_) => K1Status::Succeeded,
+            K1Outcome::Failed(_) => K1Status::Failed,
+            K1Outcome::Cancelled(_) => K1Status::Cancelled,
+        }
+    }
+
+    pub fn detail(&self) -> &str {
+        match self {
+            K1Outcome::Succeeded(detail) | K1Outcome::Failed(detail) | K1Outcome::Cancelled(detail) => detail,
+        }
+    }
+}
+
+/// Everything stored about a k1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct K1Record {
+    pub purpose: K1Purpose,
+    pub status: K1Status,
+    pub outcome: Option<String>, // detail of the `K1Outcome`, once recorded
+    pub expires_at: u64, // unix timestamp, in seconds
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum K1Error {
+    Unknown,
+    WrongKind(K1Kind), // issued for this other flow
+    InProgress, // another callback holds it
+    Used,
+    Cancelled,
+    Expired,
+}
+
+impl fmt::Display for K1Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            K1Error::Unknown => write!(f, "k1 was never issued or has been evicted"),
+            K1Error::WrongKind(kind) => write!(f, "k1 was issued for a {} request", kind),
+            K1Error::InProgress => write!(f, "k1 is being used by another callback"),
+            K1Error::Used => write!(f, "k1 has already been used"),
+            K1Error::Cancelled => write!(f, "k1 was cancelled"),
+            K1Error::Expired => write!(f, "k1 has expired"),
+        }
+    }
+}
+
+impl std::error::Error for K1Error {}
+
+impl K1Record {
+    /// Check that a `kind` callback may consume this k1 at `now`
+    pub fn check_consumable(&self, kind: K1Kind, now: u64) -> Result<(), K1Error> {
+        if self.purpose.kind() != kind {
+            return Err(K1Error::WrongKind(self.purpose.kind()));
+        }
+        match self.status {
+            K1Status::Issued => {}
+            K1Status::InProgress => return Err(K1Error::InProgress),
+            K1Status::Succeeded | K1Status::Failed => return Err(K1Error::Used),
+            K1Status::Cancelled => return Err(K1Error::Cancelled),
+        }
+        if now >= self.expires_at {
+            return Err(K1Error::Expired);
+        }
+        Ok(())
+    }
+}
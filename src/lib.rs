@@ -1,5 +1,196 @@
 use serde::{Deserialize, Serialize};
+use bech32::{primitives::decode::CheckedHrpstring, Bech32, Hrp};
 use cln_rpc::primitives::Sha256;
+use secp256k1::hashes::{sha256, Hash};
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey};
+use std::fmt;
+
+pub mod backend;
+pub mod channels;
+pub mod config;
+pub mod k1;
+pub mod node_uri;
+pub mod payout;
+pub mod service;
+pub mod sessions;
+pub mod storage;
+pub mod withdraw_links;
+
+// ============================================================================
+// LUD-01: Base LNURL encoding
+// ============================================================================
+
+pub const LNURL_HRP: &str = "lnurl";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LnurlResponse {
+    pub lnurl: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LnurlError {
+    InvalidBech32,
+    InvalidHrp,
+    InvalidUrl,
+    InvalidAddress,
+}
+
+impl fmt::Display for LnurlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LnurlError::InvalidBech32 => write!(f, "not a valid bech32 string"),
+            LnurlError::InvalidHrp => write!(f, "bech32 prefix is not \"lnurl\""),
+            LnurlError::InvalidUrl => write!(f, "encoded data is not a valid URL"),
+            LnurlError::InvalidAddress => write!(f, "not a valid user@domain Lightning Address"),
+        }
+    }
+}
+
+impl std::error::Error for LnurlError {}
+
+/// Encode a URL as a bech32 `lnurl1...` string (LUD-01)
+/// LNURLs are usually longer than the 90 characters allowed by BIP-173,
+/// so the checksum is computed with the 1023-character code length
+pub fn encode_lnurl(url: &str) -> Result<String, LnurlError> {
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err(LnurlError::InvalidUrl);
+    }
+    let hrp = Hrp::parse_unchecked(LNURL_HRP);
+    bech32::encode::<Bech32>(hrp, url.as_bytes()).map_err(|_| LnurlError::InvalidBech32)
+}
+
+/// Decode a `lnurl1...` string (upper or lower case, optionally prefixed
+/// with `lightning:`) back into the URL it carries
+pub fn decode_lnurl(lnurl: &str) -> Result<String, LnurlError> {
+    let lnurl = lnurl.trim();
+    let lnurl = match lnurl.get(..10) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("lightning:") => &lnurl[10..],
+        _ => lnurl,
+    };
+
+    let checked = CheckedHrpstring::new::<Bech32>(lnurl).map_err(|_| LnurlError::InvalidBech32)?;
+    if checked.hrp() != Hrp::parse_unchecked(LNURL_HRP) {
+        return Err(LnurlError::InvalidHrp);
+    }
+
+    let url = String::from_utf8(checked.byte_iter().collect()).map_err(|_| LnurlError::InvalidUrl)?;
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err(LnurlError::InvalidUrl);
+    }
+    Ok(url)
+}
+
+// ============================================================================
+// Error responses (shared by every LUD)
+// ============================================================================
+
+pub const ERROR_STATUS: &str = "ERROR";
+
+/// `{"status":"ERROR","reason":"..."}`, shown by wallets to their user
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ErrorResponse {
+    pub status: String,
+    pub reason: String,
+}
+
+/// Every way a service request can fail, the `Display` text is the reason sent to the wallet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceError {
+    UnknownEndpoint(String),
+    UnknownK1,
+    UsedK1,
+    K1InProgress,
+    CancelledK1,
+    ExpiredK1,
+    WrongK1 { issued: k1::K1Kind, expected: k1::K1Kind },
+    InvalidRemoteId,
+    InvalidNodeId,
+    Unauthorized,
+    InvalidAuth(AuthError),
+    InvalidSession,
+    AmountOutOfBounds { min: u64, max: u64 }, // in millisatoshis
+    InvalidInvoice(String),
+    UnknownUser(String),
+    UnknownWithdrawLink(String),
+    WithdrawLinkUnavailable(withdraw_links::LinkError),
+    UnknownPayout,
+    UnknownChannelOffer(String),
+    ChannelRefused(String),
+    UnknownChannel,
+    PeerNotConnected(String),
+    PeerConnectFailed(String),
+    FundingFailed(String),
+    PaymentFailed(String),
+    InvoiceFailed(String),
+    Internal(String),
+    /// A transient failure, the k1 was released and the same callback can be retried
+    TryAgain(Box<ServiceError>),
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::UnknownEndpoint(endpoint) => write!(f, "Unknown LNURL endpoint {}", endpoint),
+            ServiceError::UnknownK1 => write!(f, "Unknown k1, request a new link"),
+            ServiceError::UsedK1 => write!(f, "This link has already been used"),
+            ServiceError::K1InProgress => write!(f, "This link is already being processed"),
+            ServiceError::CancelledK1 => write!(f, "This request was cancelled"),
+            ServiceError::ExpiredK1 => write!(f, "This link has expired, request a new one"),
+            ServiceError::WrongK1 { issued, expected } => {
+                write!(f, "This k1 was issued for a {} request, not a {} one", issued, expected)
+            }
+            ServiceError::InvalidRemoteId => write!(f, "remoteid is not a valid node public key"),
+            ServiceError::InvalidNodeId => write!(f, "node_id is not a valid node public key"),
+            ServiceError::Unauthorized => write!(f, "Missing or invalid API token"),
+            ServiceError::InvalidAuth(e) => write!(f, "Login failed: {}", e),
+            ServiceError::InvalidSession => write!(f, "Missing, invalid or expired session"),
+            ServiceError::AmountOutOfBounds { min, max } => {
+                write!(f, "Amount must be between {} and {} msat", min, max)
+            }
+            ServiceError::InvalidInvoice(e) => write!(f, "Invalid invoice: {}", e),
+            ServiceError::UnknownUser(username) => write!(f, "Unknown user {}", username),
+            ServiceError::UnknownWithdrawLink(id) => write!(f, "Unknown withdraw link {}", id),
+            ServiceError::WithdrawLinkUnavailable(e) => write!(f, "Cannot withdraw: {}", e),
+            ServiceError::UnknownChannelOffer(name) => write!(f, "Unknown channel offer {}", name),
+            ServiceError::ChannelRefused(e) => write!(f, "Channel refused: {}", e),
+            ServiceError::UnknownChannel => write!(f, "No channel was opened for this k1"),
+            ServiceError::PeerNotConnected(id) => {
+                write!(f, "Node {} is not connected to us, connect to the uri of the channel request first", id)
+            }
+            ServiceError::PeerConnectFailed(e) => write!(f, "Could not connect to your node: {}", e),
+            ServiceError::UnknownPayout => write!(f, "No withdraw was queued for this k1"),
+            ServiceError::FundingFailed(e) => write!(f, "Could not open the channel: {}", e),
+            ServiceError::PaymentFailed(e) => write!(f, "Could not pay the invoice: {}", e),
+            ServiceError::InvoiceFailed(e) => write!(f, "Could not create an invoice: {}", e),
+            ServiceError::Internal(e) => write!(f, "Internal error: {}", e),
+            ServiceError::TryAgain(e) => write!(f, "{}, please try again", e),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+impl From<&ServiceError> for ErrorResponse {
+    fn from(e: &ServiceError) -> Self {
+        ErrorResponse {
+            status: ERROR_STATUS.to_string(),
+            reason: e.to_string(),
+        }
+    }
+}
+
+/// Decode a service response, a `{"status":"ERROR"}` body becomes `Err(reason)`
+pub fn parse_lnurl_response<T: serde::de::DeserializeOwned>(body: &str) -> Result<T, String> {
+    let value: serde_json::Value =
+        serde_json::from_str(body).map_err(|e| format!("invalid JSON response: {}", e))?;
+
+    if value["status"].as_str().is_some_and(|s| s.eq_ignore_ascii_case(ERROR_STATUS)) {
+        let reason = value["reason"].as_str().unwrap_or("no reason given");
+        return Err(reason.to_string());
+    }
+
+    serde_json::from_value(value).map_err(|e| format!("unexpected response: {}", e))
+}
 
 // ============================================================================
 // LUD-02: Channel Request
@@ -13,6 +204,36 @@ pub struct ChannelRequestResponse {
     pub k1: String,
     pub callback: String,
     pub uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease: Option<LeaseTerms>, // liquidity the offer leases from the wallet (pas dans la spec)
+}
+
+/// Lease terms of a channel offer, as the wallet is told them
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LeaseTerms {
+    pub request_sat: u64, // inbound liquidity asked from the wallet's node
+    pub max_fee_msat: u64, // most the service pays for it
+}
+
+/// LUD flags travel as `0`/`1` in query strings
+mod flag {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(if *value { "1" } else { "0" })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+        match String::deserialize(deserializer)?.as_str() {
+            "1" | "true" => Ok(true),
+            "0" | "false" => Ok(false),
+            other => Err(D::Error::custom(format!("expected 0 or 1, got {}", other))),
+        }
+    }
+
+    pub fn is_unset(value: &bool) -> bool {
+        !*value
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -20,7 +241,16 @@ pub struct OpenChannelRequest {
     pub k1: String,
     #[serde(rename = "remoteid")]
     pub remote_id: String,
-    pub private: String,
+    #[serde(default, with = "flag")]
+    pub private: bool,
+    #[serde(default, with = "flag", skip_serializing_if = "flag::is_unset")]
+    pub cancel: bool, // the wallet abandons the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<u64>, // in satoshis, within the range of the offer (pas dans la spec)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>, // where we can reach the wallet node (pas dans la spec)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -28,6 +258,25 @@ pub struct OpenChannelResponse {
     pub status: String,
 }
 
+// Suivi d'un channel ouvert par LNURL (pas dans la spec)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChannelStatusResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub k1: Option<String>, // left out of the per-node listing, anyone can query it
+    #[serde(rename = "remoteid")]
+    pub remote_id: String,
+    pub channel_id: String,
+    pub txid: String,
+    pub outnum: u32,
+    pub capacity_sat: u64,
+    pub state: String, // awaiting_lockin, normal, closing or closed
+    pub short_channel_id: Option<String>,
+    pub opened_at: u64,
+    pub updated_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease: Option<channels::ChannelLease>,
+}
+
 // Pour le callback interne (pas dans la spec)
 #[derive(Serialize, Deserialize, Debug)]
 pub struct InternalOpenChannelRequest {
@@ -35,10 +284,17 @@ pub struct InternalOpenChannelRequest {
     pub host: String,
     pub port: u16,
     pub satoshis: u64,
+    #[serde(default)]
+    pub private: bool, // unannounced channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push_msat: Option<u64>, // [channel] push_msat by default
+    #[serde(default)]
+    pub skip_node_limits: bool, // ignore max_channels_per_node and min_open_interval_secs
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct InternalOpenChannelResponse {
+    pub k1: String, // the channel is followed at /channel-status/{k1}
     pub mindepth: Option<u32>,
     pub channel_id: Sha256,
     pub outnum: u32,
@@ -76,6 +332,17 @@ pub struct WithdrawResponse {
     pub status: String,
 }
 
+// Suivi du paiement d'un withdraw (pas dans la spec, le callback répond avant de payer)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WithdrawStatusResponse {
+    pub k1: String,
+    pub status: String, // queued, paying, succeeded or failed
+    pub attempts: u32,
+    pub payment_hash: Option<String>,
+    pub fee_msat: Option<u64>, // routing fees paid
+    pub reason: Option<String>, // last failure
+}
+
 // ============================================================================
 // LUD-04: LNURL-auth
 // ============================================================================
@@ -87,13 +354,82 @@ pub struct AuthChallengeResponse {
     pub tag: String,
     pub k1: String,
     pub action: Option<String>, // "register" | "login" | "link" | "auth"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lnurl: Option<String>, // bech32 of the callback URL carrying tag, k1 and action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>, // secret the login page waits with, never shown to the wallet (pas dans la spec)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AuthRequest {
     pub k1: String,
-    pub sig: String,
-    pub key: String,
+    pub sig: String, // hex DER-encoded ECDSA signature of the raw k1 bytes
+    pub key: String, // hex compressed linking public key
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthError {
+    InvalidK1,
+    InvalidKey,
+    InvalidSignature,
+    VerificationFailed,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidK1 => write!(f, "k1 must be 32 bytes hex-encoded"),
+            AuthError::InvalidKey => write!(f, "key must be a hex compressed secp256k1 public key"),
+            AuthError::InvalidSignature => write!(f, "sig must be a hex DER-encoded ECDSA signature"),
+            AuthError::VerificationFailed => write!(f, "signature does not match k1 and key"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+fn parse_k1(k1: &str) -> Result<Message, AuthError> {
+    let bytes = hex::decode(k1).map_err(|_| AuthError::InvalidK1)?;
+    Message::from_digest_slice(&bytes).map_err(|_| AuthError::InvalidK1)
+}
+
+impl AuthRequest {
+    /// Decode and validate `k1`, `sig` and `key` without checking the signature itself
+    pub fn parse(&self) -> Result<(Message, Signature, PublicKey), AuthError> {
+        let message = parse_k1(&self.k1)?;
+
+        // LUD-04 requires the compressed form (33 bytes, 02/03 prefix)
+        let key_bytes = hex::decode(&self.key).map_err(|_| AuthError::InvalidKey)?;
+        if key_bytes.len() != 33 {
+            return Err(AuthError::InvalidKey);
+        }
+        let key = PublicKey::from_slice(&key_bytes).map_err(|_| AuthError::InvalidKey)?;
+
+        let sig_bytes = hex::decode(&self.sig).map_err(|_| AuthError::InvalidSignature)?;
+        let mut sig = Signature::from_der(&sig_bytes).map_err(|_| AuthError::InvalidSignature)?;
+        // Some wallets emit high-S signatures, libsecp256k1 only accepts low-S
+        sig.normalize_s();
+
+        Ok((message, sig, key))
+    }
+
+    /// Verify `sig` over the raw 32-byte `k1` with the linking `key` (LUD-04)
+    pub fn verify(&self) -> Result<(), AuthError> {
+        let (message, sig, key) = self.parse()?;
+        Secp256k1::verification_only()
+            .verify_ecdsa(&message, &sig, &key)
+            .map_err(|_| AuthError::VerificationFailed)
+    }
+}
+
+/// Sign a k1 challenge with a linking key, as a LUD-04 wallet would
+/// Returns `(sig, key)` ready to be sent to the callback
+pub fn sign_auth_challenge(k1: &str, linking_key: &SecretKey) -> Result<(String, String), AuthError> {
+    let message = parse_k1(k1)?;
+    let secp = Secp256k1::signing_only();
+    let sig = secp.sign_ecdsa(&message, linking_key);
+    let key = PublicKey::from_secret_key(&secp, linking_key);
+    Ok((hex::encode(sig.serialize_der()), hex::encode(key.serialize())))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -101,3 +437,107 @@ pub struct AuthResponse {
     pub status: String,
     pub event: Option<String>, // "REGISTERED" | "LOGGEDIN" | "LINKED" | "AUTHED"
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthSessionRequest {
+    pub k1: String,
+    pub session: String, // secret given with the challenge
+}
+
+/// Where the login of a challenge stands, polled by the page showing its QR
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthSessionResponse {
+    pub status: String,
+    pub state: String, // pending, loggedin or failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>, // linking key, once logged in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>, // bearer token of the session, once logged in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>, // of the session, unix timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>, // why the login failed
+}
+
+/// The session a bearer token belongs to
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionResponse {
+    pub key: String, // linking key
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+// ============================================================================
+// LUD-06: Pay Request
+// ============================================================================
+
+pub const PAY_REQUEST_TAG: &str = "payRequest";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PayRequestResponse {
+    pub tag: String,
+    pub callback: String,
+    #[serde(rename = "minSendable")]
+    pub min_sendable: u64, // in millisatoshis
+    #[serde(rename = "maxSendable")]
+    pub max_sendable: u64, // in millisatoshis
+    pub metadata: String, // JSON array of [mime, content] entries, as a string
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PayCallbackRequest {
+    pub amount: u64, // in millisatoshis
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PayCallbackResponse {
+    pub pr: String, // BOLT11 invoice whose description hash commits to metadata
+    #[serde(default)]
+    pub routes: Vec<serde_json::Value>, // always empty, kept for older wallets
+}
+
+/// Build the LUD-06 metadata string for a plain text description
+/// `identifier` is the Lightning Address the request was served for (LUD-16)
+pub fn pay_metadata(description: &str, identifier: Option<&str>) -> String {
+    let mut entries = vec![("text/plain", description)];
+    if let Some(identifier) = identifier {
+        entries.push(("text/identifier", identifier));
+    }
+    serde_json::to_string(&entries).expect("metadata entries are serializable")
+}
+
+/// SHA256 of the metadata string, which the invoice description hash must equal
+pub fn metadata_hash(metadata: &str) -> Sha256 {
+    sha256::Hash::hash(metadata.as_bytes())
+}
+
+// ============================================================================
+// LUD-16: Lightning Address
+// ============================================================================
+
+/// Usernames allowed by LUD-16: a-z0-9-_.+
+pub fn is_valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-_.+".contains(c))
+}
+
+/// Resolve `user@domain` into the URL of its payRequest
+/// Onion and local domains are served over plain http, everything else over https
+pub fn lightning_address_url(address: &str) -> Result<String, LnurlError> {
+    let (username, domain) = address.trim().split_once('@').ok_or(LnurlError::InvalidAddress)?;
+    let username = username.to_lowercase();
+    if !is_valid_username(&username) || domain.is_empty() || domain.contains(['/', '@']) {
+        return Err(LnurlError::InvalidAddress);
+    }
+
+    let host = domain.split(':').next().unwrap_or(domain);
+    let scheme = if host.ends_with(".onion") || host == "localhost" || host == "127.0.0.1" {
+        "http"
+    } else {
+        "https"
+    };
+
+    Ok(format!("{}://{}/.well-known/lnurlp/{}", scheme, domain, username))
+}
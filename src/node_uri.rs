@@ -0,0 +1,70 @@
+//! Node URI advertised by LNURL-channel, derived from the node's own getinfo
+
+use crate::backend::{AddressKind, LightningBackend, NodeAddress, NodeInfo};
+use crate::config::parse_node_uri;
+
+/// Address kinds advertised when the config does not say otherwise, in order of preference
+pub const DEFAULT_ADVERTISE: &[AddressKind] = &[AddressKind::Ipv4, AddressKind::Ipv6, AddressKind::TorV3];
+
+fn format_uri(info: &NodeInfo, address: &NodeAddress) -> String {
+    match address.kind {
+        AddressKind::Ipv6 => format!("{}@[{}]:{}", info.id, address.address, address.port),
+        _ => format!("{}@{}:{}", info.id, address.address, address.port),
+    }
+}
+
+/// Pick the first announced address whose kind comes first in `policy`
+pub fn select_node_uri(info: &NodeInfo, policy: &[AddressKind]) -> Option<String> {
+    policy.iter().find_map(|kind| {
+        info.addresses
+            .iter()
+            .find(|a| a.kind == *kind)
+            .map(|a| format_uri(info, a))
+    })
+}
+
+/// Check that a configured URI points at this node and, when the node
+/// announces addresses, at one of them
+pub fn check_node_uri(info: &NodeInfo, uri: &str) -> Result<(), String> {
+    let (id, host, port) = parse_node_uri(uri)?;
+    if id != info.id {
+        return Err(format!("node.uri has id {} but the node is {}", id, info.id));
+    }
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let announced = info.addresses.is_empty()
+        || info.addresses.iter().any(|a| a.address == host && a.port == port);
+    if !announced {
+        let addresses: Vec<_> = info.addresses.iter().map(|a| format_uri(info, a)).collect();
+        return Err(format!(
+            "node.uri {} is not announced by the node (announced: {})",
+            uri,
+            addresses.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// The URI to advertise: the configured one once checked against the node,
+/// otherwise the best announced address according to `policy`
+pub async fn resolve_node_uri(
+    backend: &dyn LightningBackend,
+    configured: Option<&str>,
+    policy: &[AddressKind],
+) -> Result<String, String> {
+    let info = backend
+        .get_info()
+        .await
+        .map_err(|e| format!("getinfo failed: {}", e))?;
+
+    match configured {
+        Some(uri) => check_node_uri(&info, uri).map(|_| uri.to_string()),
+        None => select_node_uri(&info, policy).ok_or_else(|| {
+            format!(
+                "node {} announces no address of kind {:?}, set node.uri",
+                info.id, policy
+            )
+        }),
+    }
+}
@@ -0,0 +1,78 @@
+//! Asynchronous withdraw payouts (LUD-03)
+//!
+//! The withdraw callback answers as soon as the invoice is accepted and queues
+//! a `Payout`. A worker pays it in the background, retries transient failures
+//! a bounded number of times, then records the outcome against the k1 so the
+//! wallet can query it. Payouts are kept by the `crate::storage::Storage`, a
+//! restart resumes the ones that were queued or being paid.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Lifecycle of a payout: queued → paying → succeeded or failed
+/// A transient failure puts it back in the queue until its attempts run out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayoutStatus {
+    Queued,
+    Paying,
+    Succeeded,
+    Failed,
+}
+
+impl PayoutStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayoutStatus::Queued => "queued",
+            PayoutStatus::Paying => "paying",
+            PayoutStatus::Succeeded => "succeeded",
+            PayoutStatus::Failed => "failed",
+        }
+    }
+
+    pub fn is_final(&self) -> bool {
+        matches!(self, PayoutStatus::Succeeded | PayoutStatus::Failed)
+    }
+}
+
+impl fmt::Display for PayoutStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for PayoutStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(PayoutStatus::Queued),
+            "paying" => Ok(PayoutStatus::Paying),
+            "succeeded" => Ok(PayoutStatus::Succeeded),
+            "failed" => Ok(PayoutStatus::Failed),
+            _ => Err(format!("unknown payout status {}", s)),
+        }
+    }
+}
+
+/// Payment of a withdraw invoice, keyed by the k1 that accepted it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Payout {
+    pub k1: String,
+    pub bolt11: String,
+    pub amount_msat: u64,
+    pub link: Option<String>, // withdraw link the amount is reserved against
+    pub status: PayoutStatus,
+    pub attempts: u32,
+    pub payment_hash: Option<String>, // once paid
+    pub fee_msat: Option<u64>, // routing fees paid
+    pub error: Option<String>, // last failure
+    pub next_attempt_at: u64, // unix timestamp, in seconds
+}
+
+/// How one attempt at paying a payout ended
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PayoutResult {
+    Paid { payment_hash: String, fee_msat: u64 },
+    Retry { error: String, at: u64 }, // back in the queue until `at`
+    Failed { error: String },
+}
@@ -1,364 +1,242 @@
-use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    routing::get,
-    Json, Router,
-};
-use cln_rpc::{
-    model::{requests as creq, responses as cresp},
-    primitives::{Amount, AmountOrAll, PublicKey},
-    ClnRpc,
-};
-use rand::Rng;
-use std::{
-    collections::HashMap,
-    str::FromStr,
-    sync::Arc,
-};
-use tokio::sync::Mutex;
-use tracing::info;
-
-use lnurl_project::*;
-
-const PUBLIC_KEY: &str = "029249978ef61cf264d2cf57589c96780bdd86266fdc065d6b54c48d2c9ea3ad40";
-const IP_PORT: &str = "89.87.30.156:9735"; 
-const SERVER_URL: &str = "http://89.87.30.156:3000"; //server URL
-
-
-
-#[derive(Clone)]
-struct AppState {
-    client_rpc: Arc<Mutex<ClnRpc>>,
-    k1_cache: Arc<Mutex<HashMap<String, K1Data>>>,
+use clap::Parser;
+use lnurl_project::backend::ClnBackend;
+use lnurl_project::config::{Config, StorageKind};
+use lnurl_project::node_uri::resolve_node_uri;
+use lnurl_project::service::{self, AppState, ServiceConfig};
+use lnurl_project::storage::{MemoryStorage, SqliteStorage, Storage};
+use lnurl_project::withdraw_links::load_withdraw_links;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// LNURL server (LUD-02/03/04/06/16) backed by Core Lightning
+/// Flags and environment variables override the config file
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// TOML config file (optional unless given explicitly)
+    #[arg(short, long, env = "LNURL_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address the HTTP server listens on, e.g. 0.0.0.0:3000
+    #[arg(long, env = "LNURL_BIND")]
+    bind: Option<String>,
+
+    /// Public base URL wallets reach the server at, e.g. https://lnurl.example.com
+    #[arg(long, env = "LNURL_PUBLIC_URL")]
+    public_url: Option<String>,
+
+    /// Domain of the user@domain Lightning Addresses
+    #[arg(long, env = "LNURL_LN_ADDRESS_DOMAIN")]
+    ln_address_domain: Option<String>,
+
+    /// Lightning Address registry (JSON)
+    #[arg(long, env = "LNURL_USERS_FILE")]
+    users_file: Option<PathBuf>,
+
+    /// Node URI advertised to LNURL-channel wallets: pubkey@host:port (derived from getinfo when unset)
+    #[arg(long, env = "LNURL_NODE_URI")]
+    node_uri: Option<String>,
+
+    /// Bitcoin network of the node (bitcoin, testnet, testnet4, signet, regtest)
+    #[arg(long, env = "LNURL_NETWORK")]
+    network: Option<String>,
+
+    /// Core Lightning RPC socket
+    #[arg(long, env = "LNURL_RPC_PATH")]
+    rpc_path: Option<PathBuf>,
+
+    /// Bearer token of the /internal backoffice API (disabled when unset)
+    #[arg(long, env = "LNURL_INTERNAL_API_TOKEN", hide_env_values = true)]
+    internal_api_token: Option<String>,
+
+    /// Secret signing the session tokens of LNURL-auth logins (kept in the database when unset)
+    #[arg(long, env = "LNURL_SESSION_SECRET", hide_env_values = true)]
+    session_secret: Option<String>,
 }
 
-#[derive(Clone, Debug)]
-struct K1Data {
-    challenge: String,
-    used: bool,
-}
-
-
-fn generate_k1() -> String {
-    let mut rng = rand::thread_rng();
-    let random_bytes: [u8; 32] = rng.gen();
-    hex::encode(random_bytes)
-}
+const DEFAULT_CONFIG_FILE: &str = "lnurl.toml";
 
+fn load_config(args: Args) -> Result<Config, Box<dyn std::error::Error>> {
+    let mut config = match &args.config {
+        Some(path) => Config::load(path, true)?,
+        None => Config::load(DEFAULT_CONFIG_FILE.as_ref(), false)?,
+    };
 
-/// GET /channel-request
-/// Retourne les infos pour qu'un client puisse demander l'ouverture d'un channel
-async fn channel_request(State(state): State<AppState>) -> (StatusCode, Json<ChannelRequestResponse>) {
-    let k1 = generate_k1();
-    
-    // Store k1 in cache
-    {
-        let mut cache = state.k1_cache.lock().await;
-        cache.insert(k1.clone(), K1Data {
-            challenge: k1.clone(),
-            used: false,
-        });
+    if let Some(bind) = args.bind {
+        config.server.bind = bind;
+    }
+    if args.public_url.is_some() {
+        config.server.public_url = args.public_url;
+    }
+    if args.ln_address_domain.is_some() {
+        config.server.ln_address_domain = args.ln_address_domain;
+    }
+    if let Some(users_file) = args.users_file {
+        config.server.users_file = users_file;
+    }
+    if args.node_uri.is_some() {
+        config.node.uri = args.node_uri;
+    }
+    if let Some(network) = args.network {
+        config.node.network = network;
+    }
+    if args.rpc_path.is_some() {
+        config.node.rpc_path = args.rpc_path;
+    }
+    if args.internal_api_token.is_some() {
+        config.internal.api_token = args.internal_api_token;
+    }
+    if args.session_secret.is_some() {
+        config.auth.session_secret = args.session_secret;
     }
 
-    let response = ChannelRequestResponse {
-        tag: CHANNEL_REQUEST_TAG.to_string(),
-        k1: k1.clone(),
-        callback: format!("{}/channel-callback", SERVER_URL),
-        uri: format!("{}@{}", PUBLIC_KEY, IP_PORT),
-    };
-
-    info!("Channel request generated with k1: {}", k1);
-    (StatusCode::OK, Json(response))
+    Ok(config.validate()?)
 }
 
-async fn channel_callback(
-    State(state): State<AppState>,
-    Query(params): Query<OpenChannelRequest>,
-) -> Result<Json<OpenChannelResponse>, StatusCode> {
-    info!("Channel callback received: k1={}, remoteid={}", params.k1, params.remote_id);
-
-    // Verify k1
-    {
-        let mut cache = state.k1_cache.lock().await;
-        match cache.get_mut(&params.k1) {
-            Some(data) if !data.used => {
-                data.used = true;
-            }
-            Some(_) => {
-                info!("k1 already used");
-                return Err(StatusCode::BAD_REQUEST);
-            }
-            None => {
-                info!("k1 not found in cache");
-                return Err(StatusCode::BAD_REQUEST);
-            }
-        }
-    }
+//main
 
-    // Parse node_id
-    let node_id = PublicKey::from_str(&params.remote_id)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    // Open channel via Core Lightning
-    let req = creq::FundchannelRequest {
-        id: node_id,
-        amount: AmountOrAll::Amount(Amount::from_sat(100_000)), // 100k sats par défaut
-        feerate: None,
-        announce: Some(params.private != "1"), // private=1 means private channel
-        channel_type: None,
-        minconf: None,
-        utxos: None,
-        push_msat: None,
-        close_to: None,
-        request_amt: None,
-        compact_lease: None,
-        reserve: None,
-        mindepth: None,
-    };
+#[tokio::main]
+async fn main() {
+    // Initialize tracing
+    tracing_subscriber::fmt::init();
 
-    let _resp: cresp::FundchannelResponse = {
-        let mut guard = state.client_rpc.lock().await;
-        guard
-            .call_typed(&req)
-            .await
-            .map_err(|e| {
-                info!("Failed to fund channel: {:?}", e);
-                StatusCode::BAD_GATEWAY
-            })?
-    };
+    let config = load_config(Args::parse()).unwrap_or_else(|e| {
+        eprintln!("ERROR in configuration: {e}");
+        std::process::exit(1);
+    });
 
-    info!("Channel opened successfully!");
-    Ok(Json(OpenChannelResponse {
-        status: "OK".to_string(),
-    }))
-}
+    // Connect to Core Lightning
+    let rpc_path = config.node.rpc_path.clone().expect("validated config has an rpc path");
 
-/// GET /withdraw-request
-/// Retourne les infos pour qu'un client puisse demander un withdraw
-async fn withdraw_request(State(state): State<AppState>) -> (StatusCode, Json<WithdrawRequestResponse>) {
-    let k1 = generate_k1();
-    
-    // Store k1 in cache
-    {
-        let mut cache = state.k1_cache.lock().await;
-        cache.insert(k1.clone(), K1Data {
-            challenge: k1.clone(),
-            used: false,
-        });
+    let backend = ClnBackend::connect(&rpc_path.to_string_lossy()).await;
+    if let Err(e) = &backend {
+        eprintln!("ERROR connecting to Core Lightning at {}: {e}", rpc_path.display());
+        eprintln!("Make sure lightningd is running on {}!", config.node.network);
+        std::process::exit(1);
     }
 
-    let response = WithdrawRequestResponse {
-        tag: WITHDRAW_REQUEST_TAG.to_string(),
-        callback: format!("{}/withdraw-callback", SERVER_URL),
-        k1: k1.clone(),
-        default_description: "LNURL withdraw".to_string(),
-        min_withdrawable: 1_000, // 1 sat minimum (in millisats)
-        max_withdrawable: 1_000_000, // 1000 sats maximum (in millisats)
-    };
+    let backend = Arc::new(backend.unwrap());
 
-    info!("Withdraw request generated with k1: {}", k1);
-    (StatusCode::OK, Json(response))
-}
+    // Refuse to start when the configured URI does not belong to this node
+    let node_uri = resolve_node_uri(backend.as_ref(), config.node.uri.as_deref(), &config.node.advertise)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("ERROR resolving node URI: {e}");
+            std::process::exit(1);
+        });
 
-/// GET /withdraw-callback?k1=...&pr=...
-/// Callback appelé par le client pour effectivement effectuer le withdraw
-async fn withdraw_callback(
-    State(state): State<AppState>,
-    Query(params): Query<WithdrawRequest>,
-) -> Result<Json<WithdrawResponse>, StatusCode> {
-    info!("Withdraw callback received: k1={}", params.k1);
-
-    // Verify k1
-    {
-        let mut cache = state.k1_cache.lock().await;
-        match cache.get_mut(&params.k1) {
-            Some(data) if !data.used => {
-                data.used = true;
-            }
-            Some(_) => {
-                info!("k1 already used");
-                return Err(StatusCode::BAD_REQUEST);
-            }
-            None => {
-                info!("k1 not found in cache");
-                return Err(StatusCode::BAD_REQUEST);
-            }
+    let storage: Arc<dyn Storage> = match config.storage.backend {
+        StorageKind::Memory => Arc::new(MemoryStorage::new(config.k1.max_entries)),
+        StorageKind::Sqlite => {
+            let path = &config.storage.path;
+            let storage = SqliteStorage::open(path, config.k1.max_entries).unwrap_or_else(|e| {
+                eprintln!("ERROR opening database {}: {e}", path.display());
+                std::process::exit(1);
+            });
+            info!("Storing k1s in {}", path.display());
+            Arc::new(storage)
         }
-    }
-
-    // Pay the invoice via Core Lightning
-    let req = creq::PayRequest {
-        bolt11: params.pr.clone(),
-        amount_msat: None,
-        label: None,
-        riskfactor: None,
-        maxfeepercent: None,
-        retry_for: None,
-        maxdelay: None,
-        exemptfee: None,
-        localinvreqid: None,
-        exclude: None,
-        maxfee: None,
-        description: None,
-        partial_msat: None,
-    };
-
-    let _resp: cresp::PayResponse = {
-        let mut guard = state.client_rpc.lock().await;
-        guard
-            .call_typed(&req)
-            .await
-            .map_err(|e| {
-                info!("Failed to pay invoice: {:?}", e);
-                StatusCode::BAD_GATEWAY
-            })?
     };
 
-    info!("Withdraw successful!");
-    Ok(Json(WithdrawResponse {
-        status: "OK".to_string(),
-    }))
-}
-
-// ============================================================================
-// LUD-04: LNURL-auth Handlers
-// ============================================================================
-
-/// GET /auth-challenge
-/// Retourne un challenge k1 pour l'authentification
-async fn auth_challenge(State(state): State<AppState>) -> (StatusCode, Json<AuthChallengeResponse>) {
-    let k1 = generate_k1();
-    
-    // Store k1 in cache
-    {
-        let mut cache = state.k1_cache.lock().await;
-        cache.insert(k1.clone(), K1Data {
-            challenge: k1.clone(),
-            used: false,
-        });
-    }
+    let users = service::load_users(&config.server.users_file).unwrap_or_else(|e| {
+        eprintln!("ERROR loading Lightning Address users: {e}");
+        std::process::exit(1);
+    });
+    info!("Loaded {} Lightning Address user(s) from {}", users.len(), config.server.users_file.display());
 
-    let response = AuthChallengeResponse {
-        tag: AUTH_TAG.to_string(),
-        k1: k1.clone(),
-        action: Some("login".to_string()),
+    let links_file = &config.withdraw.links_file;
+    let withdraw_links = load_withdraw_links(links_file).unwrap_or_else(|e| {
+        eprintln!("ERROR loading withdraw links: {e}");
+        std::process::exit(1);
+    });
+    info!("Loaded {} withdraw link(s) from {}", withdraw_links.len(), links_file.display());
+
+    // Without a configured secret, the storage keeps one: sessions last as long as it does
+    let session_secret = match config.auth.session_secret.clone() {
+        Some(secret) => secret,
+        None => storage.session_secret().await.unwrap_or_else(|e| {
+            eprintln!("ERROR reading the session secret: {e}");
+            std::process::exit(1);
+        }),
     };
-
-    info!("Auth challenge generated with k1: {}", k1);
-    (StatusCode::OK, Json(response))
-}
-
-/// GET /auth-response?k1=...&sig=...&key=...
-/// Vérifie la signature et authentifie l'utilisateur
-async fn auth_response(
-    State(state): State<AppState>,
-    Query(params): Query<AuthRequest>,
-) -> Result<Json<AuthResponse>, StatusCode> {
-    info!("Auth response received: k1={}, key={}", params.k1, params.key);
-
-    // Verify k1 exists and is not used
-    {
-        let mut cache = state.k1_cache.lock().await;
-        match cache.get_mut(&params.k1) {
-            Some(data) if !data.used => {
-                data.used = true;
-            }
-            Some(_) => {
-                info!("k1 already used");
-                return Err(StatusCode::BAD_REQUEST);
-            }
-            None => {
-                info!("k1 not found in cache");
-                return Err(StatusCode::BAD_REQUEST);
-            }
-        }
+    if config.auth.session_secret.is_none() && config.storage.backend == StorageKind::Memory {
+        warn!("auth.session_secret is not set, login sessions end when the server restarts");
     }
 
-    // Parse the public key
-    let pubkey = PublicKey::from_str(&params.key)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    // Verify signature using Core Lightning's checkmessage
-    let req = creq::CheckmessageRequest {
-        message: params.k1.clone(),
-        zbase: params.sig.clone(),
-        pubkey: Some(pubkey),
+    let service_config = ServiceConfig {
+        server_url: config.server.public_url.clone().expect("validated config has a public URL"),
+        node_uri: node_uri.clone(),
+        ln_address_domain: config.server.ln_address_domain.clone().expect("validated config has a domain"),
+        network: config.node.network.clone(),
+        legacy_zbase_auth: config.auth.legacy_zbase,
+        internal_api_token: config.internal.api_token.clone(),
+        session_secret,
+        session_ttl_secs: config.auth.session_ttl_secs,
+        k1: config.k1.clone(),
+        channel: config.channel.clone(),
+        channel_offers: config.channel_offers.clone().into_iter().collect(),
+        channel_policy: config.channel_policy.clone(),
+        withdraw: config.withdraw.clone(),
+        pay: config.pay.clone(),
     };
 
-    let resp: cresp::CheckmessageResponse = {
-        let mut guard = state.client_rpc.lock().await;
-        guard
-            .call_typed(&req)
-            .await
-            .map_err(|e| {
-                info!("Failed to verify signature: {:?}", e);
-                StatusCode::UNAUTHORIZED
-            })?
-    };
+    let shared_state = AppState::new(backend, storage, service_config, users).with_withdraw_links(withdraw_links);
 
-    if !resp.verified {
-        info!("Signature verification failed");
-        return Err(StatusCode::UNAUTHORIZED);
+    service::spawn_k1_sweeper(shared_state.clone(), Duration::from_secs(config.k1.sweep_secs));
+    service::spawn_payout_worker(shared_state.clone(), config.payout.clone());
+    if config.node.channel_poll_secs > 0 {
+        service::spawn_channel_watcher(shared_state.clone(), Duration::from_secs(config.node.channel_poll_secs));
     }
 
-    info!("Auth successful for key: {}", params.key);
-    
-    Ok(Json(AuthResponse {
-        status: "OK".to_string(),
-        event: Some("LOGGEDIN".to_string()),
-    }))
-}
- 
-
-//main 
-
-#[tokio::main]
-async fn main() {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
-
-    // Connect to Core Lightning
-    let home = std::env::var("HOME").expect("HOME env var not set");
-    let rpc_path = format!("{home}/.lightning/testnet4/lightning-rpc");
-
-    let client = ClnRpc::new(&rpc_path).await;
-    if let Err(e) = &client {
-        eprintln!("ERROR connecting to Core Lightning: {e}");
-        eprintln!("Make sure lightningd is running on testnet4!");
-        std::process::exit(1);
+    if config.node.refresh_secs > 0 {
+        service::spawn_node_uri_refresh(
+            shared_state.clone(),
+            config.node.uri.clone(),
+            config.node.advertise.clone(),
+            Duration::from_secs(config.node.refresh_secs),
+        );
     }
 
-    let shared_state = AppState {
-        client_rpc: Arc::new(Mutex::new(client.unwrap())),
-        k1_cache: Arc::new(Mutex::new(HashMap::new())),
-    };
-
     // Build router
-    let app = Router::new()
-        // LUD-02: Channel Request
-        .route("/channel-request", get(channel_request))
-        .route("/channel-callback", get(channel_callback))
-        // LUD-03: Withdraw Request
-        .route("/withdraw-request", get(withdraw_request))
-        .route("/withdraw-callback", get(withdraw_callback))
-        // LUD-04: LNURL-auth
-        .route("/auth-challenge", get(auth_challenge))
-        .route("/auth-response", get(auth_response))
-        .with_state(shared_state);
+    let app = service::router(shared_state);
 
     // Run server
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
+    let listener = tokio::net::TcpListener::bind(&config.server.bind)
         .await
-        .expect("Failed to bind to port 3000");
+        .unwrap_or_else(|e| {
+            eprintln!("ERROR binding {}: {e}", config.server.bind);
+            std::process::exit(1);
+        });
 
-    info!("🚀 Server running on http://0.0.0.0:3000");
+    info!("🚀 Server running on http://{} ({})", config.server.bind, config.node.network);
+    info!("🌍 Public URL: {}", config.server.public_url.as_deref().unwrap_or_default());
+    info!("⚡ Node URI: {}", node_uri);
     info!("📡 Endpoints:");
+    info!("  - GET  /lnurl/{{endpoint}}");
+    info!("  - GET  /lnurl/{{endpoint}}/{{name}}");
     info!("  - GET  /channel-request");
+    info!("  - GET  /channel-request/{{offer}}");
     info!("  - GET  /channel-callback");
+    info!("  - GET  /channel-status/{{k1}}");
+    info!("  - GET  /channel-status/node/{{remoteid}}");
     info!("  - GET  /withdraw-request");
+    info!("  - GET  /withdraw-request/{{link_id}}");
     info!("  - GET  /withdraw-callback");
+    info!("  - GET  /withdraw-status/{{k1}}");
     info!("  - GET  /auth-challenge");
     info!("  - GET  /auth-response");
+    info!("  - GET  /auth-session");
+    info!("  - GET  /session");
+    info!("  - POST /logout");
+    info!("  - GET  /pay-request");
+    info!("  - GET  /pay-callback");
+    info!("  - GET  /.well-known/lnurlp/{{username}}");
+    info!("  - GET  /pay-callback/{{username}}");
+    if config.internal.api_token.is_some() {
+        info!("  - POST /internal/open-channel");
+    }
 
     axum::serve(listener, app)
         .await
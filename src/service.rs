@@ -0,0 +1,1606 @@
+//! LNURL service: HTTP handlers and their shared state
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use rand::Rng;
+use secp256k1::PublicKey;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::{Mutex, Notify, RwLock, Semaphore};
+use tracing::{info, warn};
+
+use crate::backend::{
+    bolt11_currency, AddressKind, BackendError, ChannelState, DecodedInvoice, FundChannelParams, InvoiceParams,
+    LightningBackend, PayInvoiceParams, Payment, PaymentStatus,
+};
+use crate::channels::{ChannelLease, OpenedChannel};
+use crate::config::parse_node_uri;
+use crate::config::{ChannelOffer, ChannelPolicy, K1Settings, PayLimits, PayoutSettings, WithdrawLimits};
+use crate::k1::{K1Error, K1Kind, K1Outcome, K1Purpose, K1Status};
+use crate::payout::{Payout, PayoutResult};
+use crate::sessions::{self, same_secret, AuthSession};
+use crate::storage::{Storage, StorageError};
+use crate::withdraw_links::WithdrawLink;
+use crate::*;
+
+/// What the service advertises in its LNURL responses
+#[derive(Clone, Debug)]
+pub struct ServiceConfig {
+    pub server_url: String, // public base URL, callbacks are built from it
+    pub node_uri: String, // pubkey@host:port advertised by channel requests, until refreshed
+    pub ln_address_domain: String, // domain part of user@domain addresses
+    pub network: String, // invoices of other networks are rejected
+    pub legacy_zbase_auth: bool, // accept Core Lightning zbase32 signatures when sig is not DER
+    pub internal_api_token: Option<String>, // /internal is disabled without it
+    pub session_secret: String, // signs the session tokens of LNURL-auth logins
+    pub session_ttl_secs: u64,
+    pub k1: K1Settings,
+    pub channel: ChannelOffer, // served by /channel-request
+    pub channel_offers: HashMap<String, ChannelOffer>, // served by /channel-request/{name}
+    pub channel_policy: ChannelPolicy,
+    pub withdraw: WithdrawLimits,
+    pub pay: PayLimits,
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    backend: Arc<dyn LightningBackend>,
+    config: Arc<ServiceConfig>,
+    node_uri: Arc<RwLock<String>>,
+    storage: Arc<dyn Storage>,
+    users: Arc<HashMap<String, LightningAddressUser>>,
+    withdraw_links: Arc<HashMap<String, WithdrawLink>>,
+    payout_wakeup: Arc<Notify>, // new payout queued or a payout slot freed
+    channel_funding: Arc<Mutex<()>>, // one policy check and funding at a time
+}
+
+impl AppState {
+    pub fn new(
+        backend: Arc<dyn LightningBackend>,
+        storage: Arc<dyn Storage>,
+        config: ServiceConfig,
+        users: HashMap<String, LightningAddressUser>,
+    ) -> Self {
+        AppState {
+            backend,
+            storage,
+            node_uri: Arc::new(RwLock::new(config.node_uri.clone())),
+            config: Arc::new(config),
+            users: Arc::new(users),
+            withdraw_links: Arc::new(HashMap::new()),
+            payout_wakeup: Arc::new(Notify::new()),
+            channel_funding: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Serve these withdraw links, keyed by link id
+    pub fn with_withdraw_links(mut self, links: HashMap<String, WithdrawLink>) -> Self {
+        self.withdraw_links = Arc::new(links);
+        self
+    }
+}
+
+/// Periodically re-read getinfo so the advertised node URI follows key or address changes
+/// A configured URI is only re-checked: it is kept, with a warning, if it stops matching
+pub fn spawn_node_uri_refresh(
+    state: AppState,
+    configured: Option<String>,
+    policy: Vec<AddressKind>,
+    every: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        interval.tick().await; // first tick is immediate, startup already resolved the URI
+
+        loop {
+            interval.tick().await;
+            let resolved =
+                crate::node_uri::resolve_node_uri(state.backend.as_ref(), configured.as_deref(), &policy).await;
+
+            match resolved {
+                Ok(uri) => {
+                    let mut current = state.node_uri.write().await;
+                    if *current != uri {
+                        info!("Advertised node URI changed: {} -> {}", current, uri);
+                        *current = uri;
+                    }
+                }
+                Err(e) => warn!("Could not refresh node URI: {}", e),
+            }
+        }
+    })
+}
+
+/// Periodically drop expired k1s so the cache only holds live challenges
+pub fn spawn_k1_sweeper(state: AppState, every: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            match state.storage.sweep().await {
+                Ok(0) => {}
+                Ok(removed) => info!("Swept {} expired k1(s) and session(s)", removed),
+                Err(e) => warn!("Could not sweep expired k1s and sessions: {}", e),
+            }
+        }
+    })
+}
+
+/// Pay queued withdraw payouts in the background, at most `settings.concurrency` at once
+/// Payouts a previous run left paying are resumed, their payment is looked up before paying
+pub fn spawn_payout_worker(state: AppState, settings: PayoutSettings) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        match state.storage.requeue_payouts().await {
+            Ok(0) => {}
+            Ok(requeued) => info!("Resuming {} interrupted payout(s)", requeued),
+            Err(e) => warn!("Could not resume interrupted payouts: {}", e),
+        }
+
+        let settings = Arc::new(settings);
+        let slots = Arc::new(Semaphore::new(settings.concurrency));
+        loop {
+            match state.storage.claim_payouts(slots.available_permits()).await {
+                Ok(payouts) => {
+                    for payout in payouts {
+                        let slot = slots.clone().acquire_owned().await.expect("semaphore is never closed");
+                        let (state, settings) = (state.clone(), settings.clone());
+                        tokio::spawn(async move {
+                            run_payout(&state, payout, &settings).await;
+                            drop(slot);
+                            state.payout_wakeup.notify_one();
+                        });
+                    }
+                }
+                Err(e) => warn!("Could not claim payouts: {}", e),
+            }
+
+            // Woken by new payouts and finished ones, the timeout picks up due retries
+            let _ = tokio::time::timeout(Duration::from_secs(1), state.payout_wakeup.notified()).await;
+        }
+    })
+}
+
+/// Follow the channels opened through LNURL until they are closed, polling the node
+pub fn spawn_channel_watcher(state: AppState, every: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            if let Err(e) = refresh_channels(&state).await {
+                warn!("Could not refresh channel states: {}", e);
+            }
+        }
+    })
+}
+
+/// Record the state the node reports for every channel that is not closed yet,
+/// a channel the node forgot about is closed
+async fn refresh_channels(state: &AppState) -> Result<(), String> {
+    let followed = state.storage.open_channels().await.map_err(|e| e.to_string())?;
+    if followed.is_empty() {
+        return Ok(());
+    }
+
+    let listed = state.backend.list_channels().await.map_err(|e| e.to_string())?;
+    for channel in followed {
+        let (new_state, short_channel_id) = match listed.iter().find(|c| c.channel_id == channel.channel_id) {
+            Some(info) => (info.state, info.short_channel_id.as_deref()),
+            None => (ChannelState::Closed, None),
+        };
+        if new_state == channel.state && (short_channel_id.is_none() || channel.short_channel_id.is_some()) {
+            continue;
+        }
+
+        info!("Channel {} to {}: {} -> {}", channel.channel_id, channel.remote_id, channel.state, new_state);
+        state
+            .storage
+            .update_channel(&channel.channel_id, new_state, short_channel_id)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// One attempt at paying `payout`, a final outcome is recorded against its k1
+async fn run_payout(state: &AppState, payout: Payout, settings: &PayoutSettings) {
+    // A withdraw link may route under its own limits
+    let link = payout.link.as_ref().and_then(|id| state.withdraw_links.get(id));
+    let fees = match link {
+        Some(link) => settings.fees.merged(&link.fees),
+        None => settings.fees.clone(),
+    };
+    let req = PayInvoiceParams {
+        bolt11: payout.bolt11.clone(),
+        max_fee_msat: fees.max_fee_for(payout.amount_msat),
+        retry_for_secs: fees.retry_for_secs,
+        max_delay: fees.max_delay,
+        exclude: fees.exclude,
+    };
+
+    let result = pay_payout(state, &payout, req, settings).await;
+    if let Err(e) = state.storage.finish_payout(&payout.k1, &result).await {
+        warn!("Could not record the payout of k1 {}: {}", payout.k1, e);
+    }
+
+    let outcome = match result {
+        PayoutResult::Paid { payment_hash, fee_msat } => {
+            info!("Withdraw paid for k1 {} ({} msat of fees)", payout.k1, fee_msat);
+            K1Outcome::Succeeded(payment_hash)
+        }
+        PayoutResult::Retry { error, .. } => {
+            info!("Withdraw payout of k1 {} failed (attempt {}), retrying: {}", payout.k1, payout.attempts, error);
+            return;
+        }
+        PayoutResult::Failed { error } => {
+            info!("Withdraw payout of k1 {} failed: {}", payout.k1, error);
+            cancel_link_withdrawal(state, payout.link.as_deref(), &payout.k1).await;
+            K1Outcome::Failed(ServiceError::PaymentFailed(error).to_string())
+        }
+    };
+    if let Err(e) = state.storage.record_outcome(&payout.k1, &outcome).await {
+        warn!("Could not settle k1 {}: {}", payout.k1, e);
+    }
+}
+
+/// Pay `payout` unless an earlier attempt is already out, and follow the payment until
+/// it settles. A payment sent before an RPC error or a restart may still complete, so
+/// the node is asked before paying again and before giving up: the invoice is neither
+/// paid twice nor its withdrawal cancelled while it can settle
+async fn pay_payout(
+    state: &AppState,
+    payout: &Payout,
+    req: PayInvoiceParams,
+    settings: &PayoutSettings,
+) -> PayoutResult {
+    let retry_at = chrono::Utc::now().timestamp() as u64 + settings.retry_secs * payout.attempts as u64;
+    let retry = |error: String| PayoutResult::Retry { error, at: retry_at };
+    let give_up = |e: BackendError| {
+        if e.is_retryable_payment() && payout.attempts < settings.max_attempts {
+            retry(e.to_string())
+        } else {
+            PayoutResult::Failed { error: e.to_string() }
+        }
+    };
+
+    let payment_hash = match state.backend.decode_invoice(&payout.bolt11).await {
+        Ok(decoded) => decoded.payment_hash,
+        Err(e) => return give_up(e),
+    };
+    // Without an answer from the node nothing is known about earlier attempts, try later
+    let sent = match state.backend.payment_status(&payment_hash).await {
+        Ok(payment) => payment.filter(|p| p.status != PaymentStatus::Failed),
+        Err(e) => return retry(format!("could not look up payment {}: {}", payment_hash, e)),
+    };
+    let payment = match sent {
+        Some(payment) => payment,
+        None => match state.backend.pay_invoice(req).await {
+            Ok(payment) => payment,
+            Err(e) => match state.backend.payment_status(&payment_hash).await {
+                Ok(Some(payment)) if payment.status != PaymentStatus::Failed => payment,
+                Ok(_) => return give_up(e),
+                Err(lookup) => return retry(format!("{} (could not look up payment: {})", e, lookup)),
+            },
+        },
+    };
+
+    let payment = wait_settled(state, payment, settings).await;
+    match payment.status {
+        PaymentStatus::Complete => PayoutResult::Paid {
+            fee_msat: payment.amount_sent_msat.saturating_sub(payment.amount_msat),
+            payment_hash: payment.payment_hash,
+        },
+        // Still in flight: give the slot back, the next attempt follows the same payment
+        PaymentStatus::Pending => PayoutResult::Retry {
+            error: format!("payment {} still pending", payment.payment_hash),
+            at: chrono::Utc::now().timestamp() as u64 + settings.pending_poll_secs,
+        },
+        PaymentStatus::Failed => PayoutResult::Failed {
+            error: format!("payment {} failed", payment.payment_hash),
+        },
+    }
+}
+
+/// Shortest wait between two looks at a pending payment
+const MIN_PAYMENT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Poll a pending payment until it completes or fails, for at most `pending_wait_secs`.
+/// HTLCs in flight can take as long as their CLTV to resolve, the payment may be returned still pending
+async fn wait_settled(state: &AppState, mut payment: Payment, settings: &PayoutSettings) -> Payment {
+    let every = Duration::from_secs(settings.pending_poll_secs).max(MIN_PAYMENT_POLL_INTERVAL);
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(settings.pending_wait_secs);
+    while payment.status == PaymentStatus::Pending {
+        tokio::time::sleep(every).await;
+        match state.backend.payment_status(&payment.payment_hash).await {
+            Ok(Some(latest)) => payment = latest,
+            Ok(None) => {}
+            Err(e) => warn!("Could not look up pending payment {}: {}", payment.payment_hash, e),
+        }
+        if tokio::time::Instant::now() >= deadline {
+            break;
+        }
+    }
+    payment
+}
+
+/// Entry of the Lightning Address registry (users.json), keyed by username
+/// Unset limits fall back to the `[pay]` ones
+#[derive(Clone, Debug, Deserialize)]
+pub struct LightningAddressUser {
+    pub description: String,
+    pub min_sendable: Option<u64>, // in millisatoshis
+    pub max_sendable: Option<u64>, // in millisatoshis
+}
+
+impl LightningAddressUser {
+    fn limits(&self, pay: &PayLimits) -> (u64, u64) {
+        (
+            self.min_sendable.unwrap_or(pay.min_sendable),
+            self.max_sendable.unwrap_or(pay.max_sendable),
+        )
+    }
+}
+
+/// Load the Lightning Address registry, an absent file means no users
+pub fn load_users(path: &std::path::Path) -> Result<HashMap<String, LightningAddressUser>, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(format!("cannot read {}: {e}", path.display())),
+    };
+    let path = path.display();
+
+    let users: HashMap<String, LightningAddressUser> =
+        serde_json::from_str(&content).map_err(|e| format!("invalid {path}: {e}"))?;
+
+    for (username, user) in &users {
+        if !is_valid_username(username) {
+            return Err(format!("invalid username {username:?} in {path}"));
+        }
+        if let (Some(min), Some(max)) = (user.min_sendable, user.max_sendable) {
+            if min > max {
+                return Err(format!("min_sendable > max_sendable for {username} in {path}"));
+            }
+        }
+    }
+
+    Ok(users)
+}
+
+/// LUD error responses are plain JSON, wallets only read `reason`
+impl IntoResponse for ServiceError {
+    fn into_response(self) -> Response {
+        info!("Request failed: {}", self);
+        // LUD errors are served with 200, wallets read the status field
+        let status = match self {
+            ServiceError::Unauthorized | ServiceError::InvalidSession => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::OK,
+        };
+        (status, Json(ErrorResponse::from(&self))).into_response()
+    }
+}
+
+/// Issue a fresh k1 for `purpose`, valid for `ttl_secs`
+async fn issue_k1(state: &AppState, purpose: K1Purpose, ttl_secs: u64) -> Result<String, ServiceError> {
+    let k1 = generate_k1();
+    state
+        .storage
+        .insert_k1(&k1, &purpose, Duration::from_secs(ttl_secs))
+        .await
+        .map_err(|e| ServiceError::Internal(e.to_string()))?;
+    Ok(k1)
+}
+
+/// Take a `kind` k1 (issued → in progress) and return what it was issued with, failing
+/// if it was never issued, issued for another flow, held by another callback, already
+/// consumed or expired
+async fn consume_k1(state: &AppState, k1: &str, kind: K1Kind) -> Result<K1Purpose, ServiceError> {
+    state.storage.consume_k1(k1, kind).await.map_err(|e| match e {
+        StorageError::K1(K1Error::Unknown) => ServiceError::UnknownK1,
+        StorageError::K1(K1Error::WrongKind(issued)) => ServiceError::WrongK1 { issued, expected: kind },
+        StorageError::K1(K1Error::InProgress) => ServiceError::K1InProgress,
+        StorageError::K1(K1Error::Used) => ServiceError::UsedK1,
+        StorageError::K1(K1Error::Cancelled) => ServiceError::CancelledK1,
+        StorageError::K1(K1Error::Expired) => ServiceError::ExpiredK1,
+        StorageError::Link(e) => ServiceError::WithdrawLinkUnavailable(e),
+        StorageError::Database(e) => ServiceError::Internal(e),
+    })
+}
+
+/// End the callback holding `k1`: a `TryAgain` failure releases it for a retry,
+/// anything else is recorded as its final outcome. The wallet already has its
+/// answer, so a storage failure is only logged
+async fn settle_k1<T, F>(state: &AppState, k1: &str, result: &Result<T, ServiceError>, detail: F)
+where
+    F: FnOnce(&T) -> String,
+{
+    let stored = match result {
+        Err(ServiceError::TryAgain(_)) => state.storage.release_k1(k1).await,
+        Ok(value) => state.storage.record_outcome(k1, &K1Outcome::Succeeded(detail(value))).await,
+        Err(e) => state.storage.record_outcome(k1, &K1Outcome::Failed(e.to_string())).await,
+    };
+    if let Err(e) = stored {
+        warn!("Could not settle k1 {}: {}", k1, e);
+    }
+}
+
+
+fn generate_k1() -> String {
+    let mut rng = rand::thread_rng();
+    let random_bytes: [u8; 32] = rng.gen();
+    hex::encode(random_bytes)
+}
+
+
+/// GET /lnurl/{endpoint}
+/// Retourne le LNURL (bech32) à encoder en QR code pour channel-request, withdraw-request ou pay-request
+async fn lnurl_link(
+    State(state): State<AppState>,
+    Path(endpoint): Path<String>,
+) -> Result<Json<LnurlResponse>, ServiceError> {
+    let open = match endpoint.as_str() {
+        "channel-request" | "pay-request" => true,
+        "withdraw-request" => state.config.withdraw.open,
+        _ => false,
+    };
+    if !open {
+        return Err(ServiceError::UnknownEndpoint(endpoint));
+    }
+
+    let lnurl = encode_lnurl(&format!("{}/{}", state.config.server_url, endpoint))
+        .map_err(|e| ServiceError::Internal(e.to_string()))?;
+
+    Ok(Json(LnurlResponse { lnurl }))
+}
+
+/// GET /lnurl/{endpoint}/{name}
+/// Retourne le LNURL d'un withdraw link ou d'une offre de channel, à imprimer ou afficher en QR code
+async fn lnurl_named_link(
+    State(state): State<AppState>,
+    Path((endpoint, name)): Path<(String, String)>,
+) -> Result<Json<LnurlResponse>, ServiceError> {
+    match endpoint.as_str() {
+        "withdraw-request" if !state.withdraw_links.contains_key(&name) => {
+            return Err(ServiceError::UnknownWithdrawLink(name));
+        }
+        "channel-request" if !state.config.channel_offers.contains_key(&name) => {
+            return Err(ServiceError::UnknownChannelOffer(name));
+        }
+        "withdraw-request" | "channel-request" => {}
+        _ => return Err(ServiceError::UnknownEndpoint(endpoint)),
+    }
+
+    let lnurl = encode_lnurl(&format!("{}/{}/{}", state.config.server_url, endpoint, name))
+        .map_err(|e| ServiceError::Internal(e.to_string()))?;
+
+    Ok(Json(LnurlResponse { lnurl }))
+}
+
+/// GET /channel-request
+/// Retourne les infos pour qu'un client puisse demander l'ouverture d'un channel
+async fn channel_request(State(state): State<AppState>) -> Result<Json<ChannelRequestResponse>, ServiceError> {
+    issue_channel(&state, state.config.channel.clone()).await
+}
+
+/// GET /channel-request/{offer}
+/// Comme /channel-request, avec les conditions d'une offre nommée
+async fn channel_offer_request(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ChannelRequestResponse>, ServiceError> {
+    let offer = state
+        .config
+        .channel_offers
+        .get(&name)
+        .ok_or_else(|| ServiceError::UnknownChannelOffer(name.clone()))?;
+
+    issue_channel(&state, offer.clone()).await
+}
+
+/// Issue a channel k1 bound to `offer`
+async fn issue_channel(state: &AppState, offer: ChannelOffer) -> Result<Json<ChannelRequestResponse>, ServiceError> {
+    let lease = offer.lease.as_ref().map(|terms| LeaseTerms {
+        request_sat: terms.request_sat,
+        max_fee_msat: terms.max_fee_msat,
+    });
+    let purpose = K1Purpose::Channel { offer };
+    let k1 = issue_k1(state, purpose, state.config.k1.channel_ttl_secs).await?;
+
+    let response = ChannelRequestResponse {
+        tag: CHANNEL_REQUEST_TAG.to_string(),
+        k1: k1.clone(),
+        callback: format!("{}/channel-callback", state.config.server_url),
+        uri: state.node_uri.read().await.clone(),
+        lease,
+    };
+
+    info!("Channel request generated with k1: {}", k1);
+    Ok(Json(response))
+}
+
+/// Transient backend failures are wrapped in `TryAgain` so the k1 gets released
+fn backend_failure(
+    e: BackendError,
+    retryable: fn(&BackendError) -> bool,
+    wrap: fn(String) -> ServiceError,
+) -> ServiceError {
+    if retryable(&e) {
+        ServiceError::TryAgain(Box::new(wrap(e.to_string())))
+    } else {
+        wrap(e.to_string())
+    }
+}
+
+/// Capacity and announcement of the channel the wallet asked `offer` for
+fn channel_terms(offer: &ChannelOffer, params: &OpenChannelRequest) -> Result<(u64, bool), ServiceError> {
+    let amount_sat = offer.capacity.pick(params.capacity).map_err(ServiceError::ChannelRefused)?;
+
+    if params.private && !offer.allow_private {
+        return Err(ServiceError::ChannelRefused("this offer only opens public channels".to_string()));
+    }
+    Ok((amount_sat, !params.private))
+}
+
+/// Interval between two checks that a wallet node has connected
+const PEER_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+async fn peer_connected(state: &AppState, node_id: &PublicKey) -> Result<bool, ServiceError> {
+    state
+        .backend
+        .is_connected(node_id)
+        .await
+        .map_err(|e| ServiceError::TryAgain(Box::new(ServiceError::Internal(e.to_string()))))
+}
+
+/// Whether `ip` can be reached on the Internet, not one of our own networks
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            let shared = a == 100 && (64..128).contains(&b); // carrier-grade NAT
+            !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_documentation()
+                || ip.is_unspecified() || ip.is_multicast() || shared || a == 0 || a >= 240)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                let [first, second, ..] = ip.segments();
+                let unique_local = first & 0xfe00 == 0xfc00;
+                let link_local = first & 0xffc0 == 0xfe80;
+                let documentation = first == 0x2001 && second == 0x0db8;
+                !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local
+                    || documentation)
+            }
+        },
+    }
+}
+
+/// Resolve the host a wallet gave, the node only dials it when every address is public.
+/// The address checked is the one dialed, a second lookup could answer another one
+async fn public_peer_address(host: &str, port: Option<u16>) -> Result<String, String> {
+    let addresses: Vec<_> = tokio::net::lookup_host((host, port.unwrap_or(9735)))
+        .await
+        .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+        .map(|address| address.ip())
+        .collect();
+    match addresses.first() {
+        Some(ip) if addresses.iter().all(|ip| is_public_ip(*ip)) => Ok(ip.to_string()),
+        Some(_) => Err(format!("{} is not a public address", host)),
+        None => Err(format!("{} has no address", host)),
+    }
+}
+
+/// Make sure `node_id` is connected before funding: the wallet should have
+/// connected to our uri, it gets `connect_grace_secs` to do so. When allowed,
+/// we connect to the host it gave instead of waiting, if it is a public address
+async fn ensure_peer_connected(
+    state: &AppState,
+    node_id: &PublicKey,
+    params: &OpenChannelRequest,
+) -> Result<(), ServiceError> {
+    if peer_connected(state, node_id).await? {
+        return Ok(());
+    }
+
+    let policy = &state.config.channel_policy;
+    if let (Some(host), true) = (&params.host, policy.outbound_connect) {
+        let connect_failed = |e: String| ServiceError::TryAgain(Box::new(ServiceError::PeerConnectFailed(e)));
+        let address = public_peer_address(host, params.port).await.map_err(connect_failed)?;
+        info!("Connecting to {} at {} ({})", node_id, host, address);
+        return state
+            .backend
+            .connect_peer(node_id, Some(&address), params.port)
+            .await
+            .map_err(|e| connect_failed(e.to_string()));
+    }
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(policy.connect_grace_secs);
+    while tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(PEER_POLL_INTERVAL).await;
+        if peer_connected(state, node_id).await? {
+            return Ok(());
+        }
+    }
+    Err(ServiceError::TryAgain(Box::new(ServiceError::PeerNotConnected(node_id.to_string()))))
+}
+
+/// Anti-abuse checks of `[channel_policy]` before funding `cost_sat` to `node_id`:
+/// channels per node, time between two opens, on-chain budget of the last 24
+/// hours and confirmed balance. Limits that lift with time let the wallet retry
+async fn check_channel_policy(state: &AppState, node_id: &PublicKey, cost_sat: u64) -> Result<(), ServiceError> {
+    check_node_limits(state, node_id).await?;
+    check_channel_funds(state, cost_sat).await
+}
+
+/// Channels `node_id` may still have, and how soon
+async fn check_node_limits(state: &AppState, node_id: &PublicKey) -> Result<(), ServiceError> {
+    let policy = &state.config.channel_policy;
+    let refused = |reason: String| ServiceError::ChannelRefused(reason);
+    let later = |reason: String| ServiceError::TryAgain(Box::new(ServiceError::ChannelRefused(reason)));
+    let storage_error = |e: StorageError| ServiceError::Internal(e.to_string());
+    let now = chrono::Utc::now().timestamp() as u64;
+
+    let channels = state.storage.channels_of(&node_id.to_string()).await.map_err(storage_error)?;
+    let open = channels.iter().filter(|c| c.state != ChannelState::Closed).count();
+    if policy.max_channels_per_node > 0 && open >= policy.max_channels_per_node as usize {
+        return Err(refused(format!(
+            "your node already has {} channel(s) with us, the limit is {}",
+            open, policy.max_channels_per_node
+        )));
+    }
+    if let Some(last) = channels.iter().map(|c| c.opened_at).max() {
+        let next = last + policy.min_open_interval_secs;
+        if now < next {
+            return Err(later(format!("a channel was opened to your node recently, try again in {} s", next - now)));
+        }
+    }
+    Ok(())
+}
+
+/// On-chain budget of the last 24 hours and confirmed balance, whoever the channel is for
+async fn check_channel_funds(state: &AppState, cost_sat: u64) -> Result<(), ServiceError> {
+    let policy = &state.config.channel_policy;
+    let later = |reason: String| ServiceError::TryAgain(Box::new(ServiceError::ChannelRefused(reason)));
+    let storage_error = |e: StorageError| ServiceError::Internal(e.to_string());
+    let now = chrono::Utc::now().timestamp() as u64;
+
+    if let Some(budget) = policy.daily_budget_sat {
+        let day_ago = now.saturating_sub(24 * 3600);
+        let funded = state.storage.channels_opened_since(day_ago).await.map_err(storage_error)?;
+        let spent: u64 = funded.iter().map(OpenedChannel::onchain_cost_sat).sum();
+        if spent + cost_sat > budget {
+            warn!("Channel budget: {} sat funded over 24h, {} more would exceed {}", spent, cost_sat, budget);
+            return Err(later("we opened all the channels we can for today, try again later".to_string()));
+        }
+    }
+
+    let balance = state
+        .backend
+        .onchain_balance()
+        .await
+        .map_err(|e| backend_failure(e, BackendError::is_retryable_funding, ServiceError::FundingFailed))?;
+    if balance.confirmed_sat < cost_sat + policy.onchain_reserve_sat {
+        warn!(
+            "Channel funding: {} sat confirmed, {} needed with the reserve",
+            balance.confirmed_sat,
+            cost_sat + policy.onchain_reserve_sat
+        );
+        return Err(later("not enough confirmed on-chain funds right now, try again later".to_string()));
+    }
+    Ok(())
+}
+
+/// Accept the rates the wallet node advertises for leasing `terms.request_sat`,
+/// as long as the lease fee stays within what the offer pays. The wallet can
+/// change its liquidity ad and retry
+async fn negotiate_lease(
+    state: &AppState,
+    node_id: &PublicKey,
+    terms: &config::LeaseTerms,
+) -> Result<ChannelLease, ServiceError> {
+    let refused = |reason: String| ServiceError::TryAgain(Box::new(ServiceError::ChannelRefused(reason)));
+    let rates = state
+        .backend
+        .lease_rates(node_id)
+        .await
+        .map_err(|e| ServiceError::TryAgain(Box::new(ServiceError::Internal(e.to_string()))))?
+        .ok_or_else(|| refused("this offer leases liquidity and your node advertises none".to_string()))?;
+
+    let fee_msat = rates.lease_fee_msat(terms.request_sat);
+    if fee_msat > terms.max_fee_msat {
+        return Err(refused(format!(
+            "your node asks {} msat to lease {} sat, this offer pays up to {} msat",
+            fee_msat, terms.request_sat, terms.max_fee_msat
+        )));
+    }
+
+    Ok(ChannelLease {
+        request_sat: terms.request_sat,
+        compact_lease: rates.compact_lease,
+        fee_msat,
+    })
+}
+
+/// Fund the channel the wallet asked `offer` for and record it
+async fn open_channel(
+    state: &AppState,
+    node_id: PublicKey,
+    offer: &ChannelOffer,
+    params: &OpenChannelRequest,
+) -> Result<OpenedChannel, ServiceError> {
+    // A request outside the offer is the wallet's mistake, let it retry
+    let (amount_sat, announce) = channel_terms(offer, params).map_err(|e| ServiceError::TryAgain(Box::new(e)))?;
+    if let Some(reason) = state.config.channel_policy.refuses(&node_id) {
+        return Err(ServiceError::ChannelRefused(reason.to_string()));
+    }
+    ensure_peer_connected(state, &node_id, params).await?;
+    let lease = match &offer.lease {
+        Some(terms) => Some(negotiate_lease(state, &node_id, terms).await?),
+        None => None,
+    };
+
+    // Concurrent callbacks must not all pass the limits before any channel is recorded
+    let _funding = state.channel_funding.lock().await;
+    let lease_fee_sat = lease.as_ref().map_or(0, |lease| lease.fee_msat.div_ceil(1000));
+    check_channel_policy(state, &node_id, amount_sat + lease_fee_sat).await?;
+
+    // Open channel via the Lightning node
+    let req = FundChannelParams {
+        node_id,
+        amount_sat,
+        announce,
+        push_msat: offer.push_msat,
+        feerate: offer.feerate.clone(),
+        channel_type: offer.channel_type,
+        minconf: offer.minconf,
+        reserve_sat: offer.reserve_sat,
+        request_amt_sat: lease.as_ref().map(|lease| lease.request_sat),
+        compact_lease: lease.as_ref().map(|lease| lease.compact_lease.clone()),
+    };
+
+    let funded = state
+        .backend
+        .fund_channel(req)
+        .await
+        .map_err(|e| backend_failure(e, BackendError::is_retryable_funding, ServiceError::FundingFailed))?;
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    let channel = OpenedChannel {
+        k1: params.k1.clone(),
+        remote_id: node_id.to_string(),
+        channel_id: funded.channel_id,
+        txid: funded.txid,
+        outnum: funded.outnum,
+        capacity_sat: amount_sat,
+        state: ChannelState::AwaitingLockin,
+        short_channel_id: None,
+        opened_at: now,
+        updated_at: now,
+        lease,
+    };
+
+    // The funding is broadcast, a storage failure only loses track of it
+    if let Err(e) = state.storage.record_channel(&channel).await {
+        warn!("Could not record channel {}: {}", channel.channel_id, e);
+    }
+    Ok(channel)
+}
+
+async fn channel_callback(
+    State(state): State<AppState>,
+    Query(params): Query<OpenChannelRequest>,
+) -> Result<Json<OpenChannelResponse>, ServiceError> {
+    info!("Channel callback received: k1={}, remoteid={}", params.k1, params.remote_id);
+
+    // Parse node_id before taking the k1, a typo must not burn it
+    let node_id = PublicKey::from_str(&params.remote_id)
+        .map_err(|_| ServiceError::InvalidRemoteId)?;
+
+    // Verify k1, the channel is opened on the terms it was issued with
+    let offer = match consume_k1(&state, &params.k1, K1Kind::Channel).await? {
+        K1Purpose::Channel { offer } => offer,
+        _ => unreachable!("consume_k1 checks the k1 kind"),
+    };
+
+    // cancel=1: the wallet gives up, the k1 cannot be used again
+    if params.cancel {
+        let outcome = K1Outcome::Cancelled(params.remote_id.clone());
+        state
+            .storage
+            .record_outcome(&params.k1, &outcome)
+            .await
+            .map_err(|e| ServiceError::Internal(e.to_string()))?;
+
+        info!("Channel request {} cancelled by {}", params.k1, params.remote_id);
+        return Ok(Json(OpenChannelResponse {
+            status: "OK".to_string(),
+        }));
+    }
+
+    let result = open_channel(&state, node_id, &offer, &params).await;
+    settle_k1(&state, &params.k1, &result, |channel| channel.txid.clone()).await;
+    let channel = result?;
+
+    info!("Channel opened successfully! txid={}", channel.txid);
+    Ok(Json(OpenChannelResponse {
+        status: "OK".to_string(),
+    }))
+}
+
+impl From<OpenedChannel> for ChannelStatusResponse {
+    fn from(channel: OpenedChannel) -> Self {
+        ChannelStatusResponse {
+            k1: Some(channel.k1),
+            remote_id: channel.remote_id,
+            channel_id: channel.channel_id,
+            txid: channel.txid,
+            outnum: channel.outnum,
+            capacity_sat: channel.capacity_sat,
+            state: channel.state.to_string(),
+            short_channel_id: channel.short_channel_id,
+            opened_at: channel.opened_at,
+            updated_at: channel.updated_at,
+            lease: channel.lease,
+        }
+    }
+}
+
+/// GET /channel-status/{k1}
+/// Où en est le channel ouvert pour ce k1
+async fn channel_status(
+    State(state): State<AppState>,
+    Path(k1): Path<String>,
+) -> Result<Json<ChannelStatusResponse>, ServiceError> {
+    let channel = state
+        .storage
+        .get_channel(&k1)
+        .await
+        .map_err(|e| ServiceError::Internal(e.to_string()))?
+        .ok_or(ServiceError::UnknownChannel)?;
+
+    Ok(Json(channel.into()))
+}
+
+/// GET /channel-status/node/{remoteid}
+/// Every channel opened through LNURL to this node. Node ids are public, so the k1s,
+/// secrets of the wallets that called back, are left out
+async fn node_channels_status(
+    State(state): State<AppState>,
+    Path(remote_id): Path<String>,
+) -> Result<Json<Vec<ChannelStatusResponse>>, ServiceError> {
+    let node_id = PublicKey::from_str(&remote_id).map_err(|_| ServiceError::InvalidRemoteId)?;
+    let channels = state
+        .storage
+        .channels_of(&node_id.to_string())
+        .await
+        .map_err(|e| ServiceError::Internal(e.to_string()))?;
+
+    let listed = channels.into_iter().map(|channel| ChannelStatusResponse {
+        k1: None,
+        ..channel.into()
+    });
+    Ok(Json(listed.collect()))
+}
+
+/// GET /withdraw-request
+/// Retourne les infos pour qu'un client puisse demander un withdraw
+async fn withdraw_request(State(state): State<AppState>) -> Result<Json<WithdrawRequestResponse>, ServiceError> {
+    let withdraw = &state.config.withdraw;
+    if !withdraw.open {
+        return Err(ServiceError::UnknownEndpoint("withdraw-request".to_string()));
+    }
+
+    issue_withdraw(
+        &state,
+        withdraw.min_withdrawable,
+        withdraw.max_withdrawable,
+        None,
+        withdraw.default_description.clone(),
+    )
+    .await
+}
+
+/// GET /withdraw-request/{link_id}
+/// Comme /withdraw-request, dans les limites de ce qu'il reste sur le link
+async fn withdraw_link_request(
+    State(state): State<AppState>,
+    Path(link_id): Path<String>,
+) -> Result<Json<WithdrawRequestResponse>, ServiceError> {
+    let link = state
+        .withdraw_links
+        .get(&link_id)
+        .ok_or_else(|| ServiceError::UnknownWithdrawLink(link_id.clone()))?;
+
+    let usage = state
+        .storage
+        .link_usage(&link_id)
+        .await
+        .map_err(|e| ServiceError::Internal(e.to_string()))?;
+    let now = chrono::Utc::now().timestamp() as u64;
+    let (min_withdrawable, max_withdrawable) = link
+        .available(&usage, now)
+        .map_err(ServiceError::WithdrawLinkUnavailable)?;
+
+    let description = link.description.clone();
+    issue_withdraw(&state, min_withdrawable, max_withdrawable, Some(link_id), description).await
+}
+
+/// Issue a withdraw k1 bound to these bounds and, if any, to the link it spends from
+async fn issue_withdraw(
+    state: &AppState,
+    min_withdrawable: u64,
+    max_withdrawable: u64,
+    link: Option<String>,
+    default_description: String,
+) -> Result<Json<WithdrawRequestResponse>, ServiceError> {
+    let purpose = K1Purpose::Withdraw {
+        min_withdrawable,
+        max_withdrawable,
+        link,
+    };
+    let k1 = issue_k1(state, purpose, state.config.k1.withdraw_ttl_secs).await?;
+
+    let response = WithdrawRequestResponse {
+        tag: WITHDRAW_REQUEST_TAG.to_string(),
+        callback: format!("{}/withdraw-callback", state.config.server_url),
+        k1: k1.clone(),
+        default_description,
+        min_withdrawable,
+        max_withdrawable,
+    };
+
+    info!("Withdraw request generated with k1: {}", k1);
+    Ok(Json(response))
+}
+
+/// Check a withdraw invoice against the terms its k1 was issued with
+async fn check_withdraw_invoice(
+    state: &AppState,
+    invoice: &DecodedInvoice,
+    min_withdrawable: u64,
+    max_withdrawable: u64,
+) -> Result<(), ServiceError> {
+    let amount = invoice
+        .amount_msat
+        .ok_or_else(|| ServiceError::InvalidInvoice("amountless invoices are not accepted".to_string()))?;
+
+    let currency = bolt11_currency(&state.config.network);
+    if invoice.currency != currency {
+        return Err(ServiceError::InvalidInvoice(format!(
+            "invoice is for another network ({}), this service pays on {}",
+            invoice.currency, state.config.network
+        )));
+    }
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    if invoice.created_at.saturating_add(invoice.expiry) <= now {
+        return Err(ServiceError::InvalidInvoice("invoice has expired".to_string()));
+    }
+
+    // The advertised URI was checked against getinfo, so its id is our node's
+    let our_id = parse_node_uri(&state.node_uri.read().await).map(|(id, _, _)| id);
+    if our_id.is_ok_and(|id| id == invoice.payee) {
+        return Err(ServiceError::InvalidInvoice("invoice is payable to this service".to_string()));
+    }
+
+    if amount < min_withdrawable || amount > max_withdrawable {
+        return Err(ServiceError::AmountOutOfBounds {
+            min: min_withdrawable,
+            max: max_withdrawable,
+        });
+    }
+    Ok(())
+}
+
+/// Reserve the invoice amount against the withdraw link of the k1, if any
+/// The link may have been spent by other k1s since this one was issued
+async fn reserve_link_withdrawal(
+    state: &AppState,
+    link_id: Option<&str>,
+    k1: &str,
+    invoice: &DecodedInvoice,
+) -> Result<(), ServiceError> {
+    let Some(link_id) = link_id else {
+        return Ok(());
+    };
+    let link = state
+        .withdraw_links
+        .get(link_id)
+        .ok_or_else(|| ServiceError::UnknownWithdrawLink(link_id.to_string()))?;
+    let amount_msat = invoice.amount_msat.unwrap_or_default(); // checked by check_withdraw_invoice
+
+    match state.storage.reserve_withdrawal(link_id, link, k1, amount_msat).await {
+        Ok(()) => Ok(()),
+        // A smaller amount or a later try may still fit
+        Err(StorageError::Link(e)) if !e.is_final() => {
+            Err(ServiceError::TryAgain(Box::new(ServiceError::WithdrawLinkUnavailable(e))))
+        }
+        Err(StorageError::Link(e)) => Err(ServiceError::WithdrawLinkUnavailable(e)),
+        Err(e) => Err(ServiceError::TryAgain(Box::new(ServiceError::Internal(e.to_string())))),
+    }
+}
+
+/// Check the invoice, reserve it against the link of the k1 and queue its payout
+async fn queue_withdraw_payout(
+    state: &AppState,
+    k1: &str,
+    bolt11: &str,
+    invoice: &DecodedInvoice,
+    (min_withdrawable, max_withdrawable, link): (u64, u64, Option<String>),
+) -> Result<(), ServiceError> {
+    // A bad invoice is the wallet's mistake, let it retry with another one
+    check_withdraw_invoice(state, invoice, min_withdrawable, max_withdrawable)
+        .await
+        .map_err(|e| ServiceError::TryAgain(Box::new(e)))?;
+    reserve_link_withdrawal(state, link.as_deref(), k1, invoice).await?;
+
+    let amount_msat = invoice.amount_msat.unwrap_or_default(); // checked by check_withdraw_invoice
+    let queued = state.storage.queue_payout(k1, bolt11, amount_msat, link.as_deref()).await;
+    if let Err(e) = queued {
+        cancel_link_withdrawal(state, link.as_deref(), k1).await;
+        return Err(ServiceError::TryAgain(Box::new(ServiceError::Internal(e.to_string()))));
+    }
+
+    state.payout_wakeup.notify_one();
+    Ok(())
+}
+
+/// Give the amount reserved by `k1` back to its link, nothing was paid
+async fn cancel_link_withdrawal(state: &AppState, link: Option<&str>, k1: &str) {
+    if link.is_some() {
+        if let Err(e) = state.storage.cancel_withdrawal(k1).await {
+            warn!("Could not cancel the withdrawal of k1 {}: {}", k1, e);
+        }
+    }
+}
+
+/// GET /withdraw-callback?k1=...&pr=...
+/// Callback appelé par le client pour effectivement effectuer le withdraw
+/// Répond dès que l'invoice est acceptée, le paiement part en arrière-plan (LUD-03)
+async fn withdraw_callback(
+    State(state): State<AppState>,
+    Query(params): Query<WithdrawRequest>,
+) -> Result<Json<WithdrawResponse>, ServiceError> {
+    info!("Withdraw callback received: k1={}", params.k1);
+
+    // Decode the invoice before taking the k1, garbage must not burn it
+    let invoice = state.backend.decode_invoice(&params.pr).await.map_err(|e| match e {
+        BackendError::Rpc { message, .. } => ServiceError::InvalidInvoice(message),
+        e => ServiceError::TryAgain(Box::new(ServiceError::Internal(e.to_string()))),
+    })?;
+
+    // Verify k1, the invoice must fit the bounds it was issued with
+    let terms = match consume_k1(&state, &params.k1, K1Kind::Withdraw).await? {
+        K1Purpose::Withdraw {
+            min_withdrawable,
+            max_withdrawable,
+            link,
+        } => (min_withdrawable, max_withdrawable, link),
+        _ => unreachable!("consume_k1 checks the k1 kind"),
+    };
+
+    // The k1 stays in progress until the payout worker records how the payment ended
+    let queued = queue_withdraw_payout(&state, &params.k1, &params.pr, &invoice, terms).await;
+    if queued.is_err() {
+        settle_k1(&state, &params.k1, &queued, |_| String::new()).await;
+    }
+    queued?;
+
+    info!("Withdraw payout queued for k1 {}", params.k1);
+    Ok(Json(WithdrawResponse {
+        status: "OK".to_string(),
+    }))
+}
+
+/// GET /withdraw-status/{k1}
+/// Où en est le paiement d'un withdraw accepté
+async fn withdraw_status(
+    State(state): State<AppState>,
+    Path(k1): Path<String>,
+) -> Result<Json<WithdrawStatusResponse>, ServiceError> {
+    let payout = state
+        .storage
+        .get_payout(&k1)
+        .await
+        .map_err(|e| ServiceError::Internal(e.to_string()))?
+        .ok_or(ServiceError::UnknownPayout)?;
+
+    Ok(Json(WithdrawStatusResponse {
+        k1: payout.k1,
+        status: payout.status.to_string(),
+        attempts: payout.attempts,
+        payment_hash: payout.payment_hash,
+        fee_msat: payout.fee_msat,
+        reason: payout.error,
+    }))
+}
+
+// ============================================================================
+// Internal API (backoffice, pas dans la spec)
+// ============================================================================
+
+/// Check the `Authorization: Bearer` token of an /internal request
+fn check_api_token(state: &AppState, headers: &HeaderMap, endpoint: &str) -> Result<(), ServiceError> {
+    let Some(expected) = &state.config.internal_api_token else {
+        return Err(ServiceError::UnknownEndpoint(endpoint.to_string()));
+    };
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(ServiceError::Unauthorized)?;
+
+    if !same_secret(given, expected) {
+        return Err(ServiceError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// POST /internal/open-channel
+/// Ouvre un channel vers host:port pour le backoffice (connect + fund), aux conditions de [channel]
+/// et de [channel_policy]. `skip_node_limits` lève seulement les limites par node
+async fn internal_open_channel(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<InternalOpenChannelRequest>,
+) -> Result<Json<InternalOpenChannelResponse>, ServiceError> {
+    check_api_token(&state, &headers, "internal/open-channel")?;
+    info!("Internal channel open: {}@{}:{} for {} sat", req.node_id, req.host, req.port, req.satoshis);
+
+    let node_id = PublicKey::from_str(&req.node_id).map_err(|_| ServiceError::InvalidNodeId)?;
+    let offer = &state.config.channel;
+    let push_msat = req.push_msat.unwrap_or(offer.push_msat);
+    if req.satoshis == 0 {
+        return Err(ServiceError::ChannelRefused("satoshis must be positive".to_string()));
+    }
+    if push_msat >= req.satoshis.saturating_mul(1000) {
+        return Err(ServiceError::ChannelRefused("push_msat must be below the capacity".to_string()));
+    }
+    if let Some(reason) = state.config.channel_policy.refuses(&node_id) {
+        return Err(ServiceError::ChannelRefused(reason.to_string()));
+    }
+
+    state
+        .backend
+        .connect_peer(&node_id, Some(&req.host), Some(req.port))
+        .await
+        .map_err(|e| ServiceError::PeerConnectFailed(e.to_string()))?;
+
+    // Same lock as the LNURL callbacks, they share the budget
+    let _funding = state.channel_funding.lock().await;
+    if req.skip_node_limits {
+        info!("Internal channel open: per-node limits skipped for {}", node_id);
+    } else {
+        check_node_limits(&state, &node_id).await?;
+    }
+    check_channel_funds(&state, req.satoshis).await?;
+
+    let params = FundChannelParams {
+        node_id,
+        amount_sat: req.satoshis,
+        announce: !req.private,
+        push_msat,
+        feerate: offer.feerate.clone(),
+        channel_type: offer.channel_type,
+        minconf: offer.minconf,
+        reserve_sat: offer.reserve_sat,
+        request_amt_sat: None,
+        compact_lease: None,
+    };
+    let funded = state
+        .backend
+        .fund_channel(params)
+        .await
+        .map_err(|e| ServiceError::FundingFailed(e.to_string()))?;
+
+    info!("Internal channel opened: txid={}", funded.txid);
+    let channel_id = Sha256::from_str(&funded.channel_id).map_err(|e| ServiceError::Internal(e.to_string()))?;
+
+    // Followed like the LNURL ones, under an id of its own
+    let now = chrono::Utc::now().timestamp() as u64;
+    let channel = OpenedChannel {
+        k1: generate_k1(),
+        remote_id: node_id.to_string(),
+        channel_id: funded.channel_id,
+        txid: funded.txid,
+        outnum: funded.outnum,
+        capacity_sat: req.satoshis,
+        state: ChannelState::AwaitingLockin,
+        short_channel_id: None,
+        opened_at: now,
+        updated_at: now,
+        lease: None,
+    };
+    if let Err(e) = state.storage.record_channel(&channel).await {
+        warn!("Could not record channel {}: {}", channel.channel_id, e);
+    }
+
+    Ok(Json(InternalOpenChannelResponse {
+        k1: channel.k1,
+        mindepth: funded.mindepth,
+        channel_id,
+        outnum: channel.outnum,
+        tx: funded.tx,
+        txid: channel.txid,
+    }))
+}
+
+// ============================================================================
+// LUD-04: LNURL-auth Handlers
+// ============================================================================
+
+/// GET /auth-challenge
+/// Retourne un challenge k1 pour l'authentification
+async fn auth_challenge(State(state): State<AppState>) -> Result<Json<AuthChallengeResponse>, ServiceError> {
+    // The page showing the QR waits for the login with this secret
+    let session = generate_k1();
+    let purpose = K1Purpose::Auth {
+        action: "login".to_string(),
+        session_hash: Some(sessions::hash_secret(&session)),
+    };
+    let k1 = issue_k1(&state, purpose, state.config.k1.auth_ttl_secs).await?;
+
+    // The wallet calls this URL directly, appending &sig=...&key=...
+    let callback = format!(
+        "{}/auth-response?tag={}&k1={}&action=login",
+        state.config.server_url, AUTH_TAG, k1
+    );
+
+    let response = AuthChallengeResponse {
+        tag: AUTH_TAG.to_string(),
+        k1: k1.clone(),
+        action: Some("login".to_string()),
+        lnurl: encode_lnurl(&callback).ok(),
+        session: Some(session),
+    };
+
+    info!("Auth challenge generated with k1: {}", k1);
+    Ok(Json(response))
+}
+
+/// Legacy mode: verify a zbase32 signature produced by Core Lightning's signmessage
+async fn verify_zbase_signature(state: &AppState, params: &AuthRequest) -> Result<(), ServiceError> {
+    let pubkey = PublicKey::from_str(&params.key)
+        .map_err(|_| ServiceError::InvalidAuth(AuthError::InvalidKey))?;
+
+    let verified = state
+        .backend
+        .check_message(&params.k1, &params.sig, &pubkey)
+        .await
+        .map_err(|e| {
+            info!("Failed to verify signature: {}", e);
+            ServiceError::InvalidAuth(AuthError::VerificationFailed)
+        })?;
+
+    if !verified {
+        return Err(ServiceError::InvalidAuth(AuthError::VerificationFailed));
+    }
+
+    Ok(())
+}
+
+/// GET /auth-response?k1=...&sig=...&key=...
+/// Vérifie la signature et authentifie l'utilisateur
+async fn auth_response(
+    State(state): State<AppState>,
+    Query(params): Query<AuthRequest>,
+) -> Result<Json<AuthResponse>, ServiceError> {
+    info!("Auth response received: k1={}, key={}", params.k1, params.key);
+
+    // Verify the DER signature of k1 with the linking key (LUD-04), before the k1 is spent:
+    // a forged or garbled signature must not burn the challenge of the wallet
+    match params.verify() {
+        Ok(()) => {}
+        Err(AuthError::InvalidSignature) if state.config.legacy_zbase_auth => {
+            verify_zbase_signature(&state, &params).await?
+        }
+        Err(e) => return Err(ServiceError::InvalidAuth(e)),
+    }
+
+    // Verify k1 exists and is not used
+    let session_hash = match consume_k1(&state, &params.k1, K1Kind::Auth).await? {
+        K1Purpose::Auth { session_hash, .. } => session_hash,
+        _ => unreachable!("consume_k1 checks the k1 kind"),
+    };
+
+    // Challenges issued with a page secret open a session for the page
+    let result = match session_hash {
+        Some(secret_hash) => open_session(&state, &params, secret_hash).await,
+        None => Ok(()),
+    };
+    settle_k1(&state, &params.k1, &result, |_| params.key.clone()).await;
+    result?;
+
+    info!("Auth successful for key: {}", params.key);
+    
+    Ok(Json(AuthResponse {
+        status: "OK".to_string(),
+        event: Some("LOGGEDIN".to_string()),
+    }))
+}
+
+/// Open the session of a successful login, the page gets its token when it polls
+async fn open_session(state: &AppState, params: &AuthRequest, secret_hash: String) -> Result<(), ServiceError> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let session = AuthSession {
+        id: generate_k1(),
+        k1: params.k1.clone(),
+        linking_key: params.key.clone(),
+        secret_hash,
+        created_at: now,
+        expires_at: now + state.config.session_ttl_secs,
+        revoked: false,
+    };
+    state
+        .storage
+        .create_session(&session)
+        .await
+        .map_err(|e| ServiceError::Internal(e.to_string()))
+}
+
+/// GET /auth-session?k1=...&session=...
+/// Où en est le login du QR, avec le token de session une fois connecté (pas dans la spec)
+async fn auth_session(
+    State(state): State<AppState>,
+    Query(params): Query<AuthSessionRequest>,
+) -> Result<Json<AuthSessionResponse>, ServiceError> {
+    let secret_hash = sessions::hash_secret(&params.session);
+    let storage_error = |e: StorageError| ServiceError::Internal(e.to_string());
+    let now = chrono::Utc::now().timestamp() as u64;
+    let response = |state: &str| AuthSessionResponse {
+        status: "OK".to_string(),
+        state: state.to_string(),
+        key: None,
+        token: None,
+        expires_at: None,
+        reason: None,
+    };
+
+    // The session outlives its k1, which is swept once expired
+    if let Some(session) = state.storage.session_of_k1(&params.k1).await.map_err(storage_error)? {
+        if !same_secret(&secret_hash, &session.secret_hash) || !session.is_active(now) {
+            return Err(ServiceError::InvalidSession);
+        }
+        return Ok(Json(AuthSessionResponse {
+            key: Some(session.linking_key.clone()),
+            token: Some(sessions::session_token(state.config.session_secret.as_bytes(), &session)),
+            expires_at: Some(session.expires_at),
+            ..response("loggedin")
+        }));
+    }
+
+    let record = state.storage.get_k1(&params.k1).await.map_err(storage_error)?.ok_or(ServiceError::UnknownK1)?;
+    match &record.purpose {
+        K1Purpose::Auth {
+            session_hash: Some(hash),
+            ..
+        } if same_secret(&secret_hash, hash) => {}
+        _ => return Err(ServiceError::InvalidSession),
+    }
+    match record.status {
+        K1Status::Failed => Ok(Json(AuthSessionResponse {
+            reason: record.outcome,
+            ..response("failed")
+        })),
+        // Logged in, but its session is gone: expired and swept
+        K1Status::Succeeded => Err(ServiceError::InvalidSession),
+        _ if now >= record.expires_at => Err(ServiceError::ExpiredK1),
+        _ => Ok(Json(response("pending"))),
+    }
+}
+
+/// The active session of an `Authorization: Bearer` token
+async fn bearer_session(state: &AppState, headers: &HeaderMap) -> Result<AuthSession, ServiceError> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let id = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| sessions::verify_token(state.config.session_secret.as_bytes(), token, now))
+        .ok_or(ServiceError::InvalidSession)?;
+
+    state
+        .storage
+        .get_session(id)
+        .await
+        .map_err(|e| ServiceError::Internal(e.to_string()))?
+        .filter(|session| session.is_active(now))
+        .ok_or(ServiceError::InvalidSession)
+}
+
+/// GET /session
+/// La session du token (pas dans la spec)
+async fn current_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<SessionResponse>, ServiceError> {
+    let session = bearer_session(&state, &headers).await?;
+    Ok(Json(SessionResponse {
+        key: session.linking_key,
+        created_at: session.created_at,
+        expires_at: session.expires_at,
+    }))
+}
+
+/// POST /logout
+/// Révoque la session du token (pas dans la spec)
+async fn logout(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<AuthResponse>, ServiceError> {
+    let session = bearer_session(&state, &headers).await?;
+    state
+        .storage
+        .revoke_session(&session.id)
+        .await
+        .map_err(|e| ServiceError::Internal(e.to_string()))?;
+
+    info!("Session of {} logged out", session.linking_key);
+    Ok(Json(AuthResponse {
+        status: "OK".to_string(),
+        event: None,
+    }))
+}
+
+// ============================================================================
+// LUD-06: Pay Request Handlers
+// ============================================================================
+
+/// GET /pay-request
+/// Retourne les infos pour qu'un wallet puisse nous payer
+async fn pay_request(State(state): State<AppState>) -> (StatusCode, Json<PayRequestResponse>) {
+    let response = PayRequestResponse {
+        tag: PAY_REQUEST_TAG.to_string(),
+        callback: format!("{}/pay-callback", state.config.server_url),
+        min_sendable: state.config.pay.min_sendable,
+        max_sendable: state.config.pay.max_sendable,
+        metadata: pay_metadata(&state.config.pay.description, None),
+    };
+
+    info!("Pay request served");
+    (StatusCode::OK, Json(response))
+}
+
+/// GET /pay-callback?amount=...
+/// Crée une invoice dont le description hash correspond aux metadata
+async fn pay_callback(
+    State(state): State<AppState>,
+    Query(params): Query<PayCallbackRequest>,
+) -> Result<Json<PayCallbackResponse>, ServiceError> {
+    info!("Pay callback received: amount={} msat", params.amount);
+
+    let pay = &state.config.pay;
+    let metadata = pay_metadata(&pay.description, None);
+    create_pay_invoice(&state, &metadata, params.amount, pay.min_sendable, pay.max_sendable).await
+}
+
+/// Create an invoice committing to `metadata` once `amount` is checked against the bounds
+async fn create_pay_invoice(
+    state: &AppState,
+    metadata: &str,
+    amount: u64,
+    min_sendable: u64,
+    max_sendable: u64,
+) -> Result<Json<PayCallbackResponse>, ServiceError> {
+    if amount < min_sendable || amount > max_sendable {
+        return Err(ServiceError::AmountOutOfBounds {
+            min: min_sendable,
+            max: max_sendable,
+        });
+    }
+
+    // The invoice only carries sha256(metadata)
+    let req = InvoiceParams {
+        amount_msat: amount,
+        description: metadata.to_string(),
+        label: format!("lnurl-pay-{}", generate_k1()),
+        expiry: Some(3600),
+        description_hash_only: true,
+    };
+
+    let resp = state
+        .backend
+        .create_invoice(req)
+        .await
+        .map_err(|e| ServiceError::InvoiceFailed(e.to_string()))?;
+
+    info!("Invoice created for {} msat", amount);
+    Ok(Json(PayCallbackResponse {
+        pr: resp.bolt11,
+        routes: vec![],
+    }))
+}
+
+// ============================================================================
+// LUD-16: Lightning Address Handlers
+// ============================================================================
+
+fn lightning_address_metadata(state: &AppState, username: &str, user: &LightningAddressUser) -> String {
+    let identifier = format!("{}@{}", username, state.config.ln_address_domain);
+    pay_metadata(&user.description, Some(&identifier))
+}
+
+/// GET /.well-known/lnurlp/{username}
+/// payRequest du user `username@ln_address_domain`
+async fn lightning_address(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Json<PayRequestResponse>, ServiceError> {
+    let username = username.to_lowercase();
+    let user = state
+        .users
+        .get(&username)
+        .ok_or_else(|| ServiceError::UnknownUser(username.clone()))?;
+
+    let (min_sendable, max_sendable) = user.limits(&state.config.pay);
+    let response = PayRequestResponse {
+        tag: PAY_REQUEST_TAG.to_string(),
+        callback: format!("{}/pay-callback/{}", state.config.server_url, username),
+        min_sendable,
+        max_sendable,
+        metadata: lightning_address_metadata(&state, &username, user),
+    };
+
+    info!("Lightning Address pay request served for {}", username);
+    Ok(Json(response))
+}
+
+/// GET /pay-callback/{username}?amount=...
+async fn lightning_address_callback(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Query(params): Query<PayCallbackRequest>,
+) -> Result<Json<PayCallbackResponse>, ServiceError> {
+    info!("Pay callback received for {}: amount={} msat", username, params.amount);
+
+    let username = username.to_lowercase();
+    let user = state
+        .users
+        .get(&username)
+        .ok_or_else(|| ServiceError::UnknownUser(username.clone()))?;
+
+    let metadata = lightning_address_metadata(&state, &username, user);
+    let (min_sendable, max_sendable) = user.limits(&state.config.pay);
+    create_pay_invoice(&state, &metadata, params.amount, min_sendable, max_sendable).await
+}
+
+/// Build the LNURL service router
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        // LUD-01: bech32 LNURLs
+        .route("/lnurl/{endpoint}", get(lnurl_link))
+        .route("/lnurl/{endpoint}/{name}", get(lnurl_named_link))
+        // LUD-02: Channel Request
+        .route("/channel-request", get(channel_request))
+        .route("/channel-request/{offer}", get(channel_offer_request))
+        .route("/channel-callback", get(channel_callback))
+        .route("/channel-status/{k1}", get(channel_status))
+        .route("/channel-status/node/{remote_id}", get(node_channels_status))
+        // LUD-03: Withdraw Request
+        .route("/withdraw-request", get(withdraw_request))
+        .route("/withdraw-request/{link_id}", get(withdraw_link_request))
+        .route("/withdraw-callback", get(withdraw_callback))
+        .route("/withdraw-status/{k1}", get(withdraw_status))
+        // LUD-04: LNURL-auth
+        .route("/auth-challenge", get(auth_challenge))
+        .route("/auth-response", get(auth_response))
+        .route("/auth-session", get(auth_session))
+        .route("/session", get(current_session))
+        .route("/logout", post(logout))
+        // LUD-06: Pay Request
+        .route("/pay-request", get(pay_request))
+        .route("/pay-callback", get(pay_callback))
+        // LUD-16: Lightning Address
+        .route("/.well-known/lnurlp/{username}", get(lightning_address))
+        .route("/pay-callback/{username}", get(lightning_address_callback))
+        // Backoffice
+        .route("/internal/open-channel", post(internal_open_channel))
+        .with_state(state)
+}
@@ -0,0 +1,59 @@
+//! Browser sessions opened by LNURL-auth (LUD-04)
+//!
+//! The page showing the login QR gets a secret along with the k1 and waits
+//! with it until a wallet signed that k1. A session is then opened for the
+//! linking key and the page gets a bearer token: the session id and its
+//! expiry, signed with the server's session secret. A token is also checked
+//! against the stored session, so logging out revokes it before it expires.
+
+use secp256k1::hashes::{hmac, sha256, Hash, HashEngine};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthSession {
+    pub id: String,
+    pub k1: String, // login challenge it was opened by
+    pub linking_key: String, // hex compressed public key that signed the k1
+    pub secret_hash: String, // sha256 of the secret the page waits with
+    pub created_at: u64, // unix timestamp, in seconds
+    pub expires_at: u64, // unix timestamp, in seconds
+    pub revoked: bool, // logged out
+}
+
+impl AuthSession {
+    /// Whether its token is still accepted at `now`
+    pub fn is_active(&self, now: u64) -> bool {
+        !self.revoked && now < self.expires_at
+    }
+}
+
+/// Compare every byte, the time taken must not tell how much of a secret matched
+pub(crate) fn same_secret(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() && given.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Only the hash of the page secret is stored, a database leak does not give it away
+pub fn hash_secret(secret: &str) -> String {
+    sha256::Hash::hash(secret.as_bytes()).to_string()
+}
+
+fn signature(key: &[u8], id: &str, expires_at: u64) -> String {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+    engine.input(format!("{}.{}", id, expires_at).as_bytes());
+    hmac::Hmac::<sha256::Hash>::from_engine(engine).to_string()
+}
+
+/// Bearer token of `session`: `<id>.<expires_at>.<hmac-sha256>`
+pub fn session_token(key: &[u8], session: &AuthSession) -> String {
+    format!("{}.{}.{}", session.id, session.expires_at, signature(key, &session.id, session.expires_at))
+}
+
+/// Session id of a token signed with `key` that has not expired at `now`
+/// The session itself still has to be looked up, it may have been revoked
+pub fn verify_token<'a>(key: &[u8], token: &'a str, now: u64) -> Option<&'a str> {
+    let mut parts = token.splitn(3, '.');
+    let (id, expires_at, given) = (parts.next()?, parts.next()?, parts.next()?);
+    let expires_at: u64 = expires_at.parse().ok()?;
+
+    let valid = same_secret(given, &signature(key, id, expires_at)) && now < expires_at;
+    valid.then_some(id)
+}
@@ -0,0 +1,282 @@
+//! In-memory storage, everything is lost on restart
+
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use super::*;
+use crate::k1::K1Status;
+
+#[derive(Default)]
+struct MemoryState {
+    entries: HashMap<String, K1Record>,
+    order: VecDeque<String>, // insertion order, oldest first
+    withdrawals: HashMap<String, Withdrawal>, // link withdrawals, by k1
+    payouts: HashMap<String, Payout>, // by k1
+    payout_order: Vec<String>, // queueing order, oldest first
+    channels: Vec<OpenedChannel>, // opening order, oldest first
+    sessions: HashMap<String, AuthSession>, // by id
+}
+
+struct Withdrawal {
+    link_id: String,
+    amount_msat: u64,
+    created_at: u64,
+}
+
+impl MemoryState {
+    fn sweep(&mut self) -> usize {
+        let now = now();
+        let before = self.entries.len();
+
+        // A k1 held by a callback stays until it is settled, expired or not
+        self.entries.retain(|_, record| now < record.expires_at || record.status == K1Status::InProgress);
+        let entries = &self.entries;
+        self.order.retain(|k1| entries.contains_key(k1));
+        let sessions = self.sessions.len();
+        self.sessions.retain(|_, session| now < session.expires_at);
+
+        before - self.entries.len() + sessions - self.sessions.len()
+    }
+
+    fn link_usage(&self, link_id: &str) -> LinkUsage {
+        self.withdrawals
+            .values()
+            .filter(|w| w.link_id == link_id)
+            .fold(LinkUsage::default(), |usage, w| LinkUsage {
+                uses: usage.uses + 1,
+                spent_msat: usage.spent_msat + w.amount_msat,
+                last_used_at: usage.last_used_at.max(Some(w.created_at)),
+            })
+    }
+}
+
+pub struct MemoryStorage {
+    state: Mutex<MemoryState>,
+    max_entries: usize,
+    session_secret: String, // lost on restart, like the sessions
+}
+
+impl MemoryStorage {
+    pub fn new(max_entries: usize) -> Self {
+        MemoryStorage {
+            state: Mutex::new(MemoryState::default()),
+            max_entries: max_entries.max(1),
+            session_secret: hex::encode(rand::random::<[u8; 32]>()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn insert_k1(&self, k1: &str, purpose: &K1Purpose, ttl: Duration) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.entries.len() >= self.max_entries {
+            state.sweep();
+        }
+        while state.entries.len() >= self.max_entries {
+            let entries = &state.entries;
+            let oldest = state.order.iter().position(|k1| entries[k1].status != K1Status::InProgress);
+            match oldest.and_then(|i| state.order.remove(i)) {
+                Some(oldest) => {
+                    state.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+
+        let record = K1Record {
+            purpose: purpose.clone(),
+            status: K1Status::Issued,
+            outcome: None,
+            expires_at: now().saturating_add(ttl.as_secs()),
+        };
+        if state.entries.insert(k1.to_string(), record).is_none() {
+            state.order.push_back(k1.to_string());
+        }
+        Ok(())
+    }
+
+    async fn consume_k1(&self, k1: &str, kind: K1Kind) -> StorageResult<K1Purpose> {
+        let mut state = self.state.lock().unwrap();
+        let record = state.entries.get_mut(k1).ok_or(K1Error::Unknown)?;
+        record.check_consumable(kind, now())?;
+
+        record.status = K1Status::InProgress;
+        Ok(record.purpose.clone())
+    }
+
+    async fn release_k1(&self, k1: &str) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let record = state.entries.get_mut(k1).ok_or(K1Error::Unknown)?;
+
+        if record.status == K1Status::InProgress {
+            record.status = K1Status::Issued;
+        }
+        Ok(())
+    }
+
+    async fn record_outcome(&self, k1: &str, outcome: &K1Outcome) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let record = state.entries.get_mut(k1).ok_or(K1Error::Unknown)?;
+
+        record.status = outcome.status();
+        record.outcome = Some(outcome.detail().to_string());
+        Ok(())
+    }
+
+    async fn get_k1(&self, k1: &str) -> StorageResult<Option<K1Record>> {
+        Ok(self.state.lock().unwrap().entries.get(k1).cloned())
+    }
+
+    async fn sweep(&self) -> StorageResult<usize> {
+        Ok(self.state.lock().unwrap().sweep())
+    }
+
+    async fn link_usage(&self, link_id: &str) -> StorageResult<LinkUsage> {
+        Ok(self.state.lock().unwrap().link_usage(link_id))
+    }
+
+    async fn reserve_withdrawal(&self, link_id: &str, link: &WithdrawLink, k1: &str, amount_msat: u64)
+        -> StorageResult<()>
+    {
+        let mut state = self.state.lock().unwrap();
+        let now = now();
+        link.check(&state.link_usage(link_id), amount_msat, now)?;
+
+        let withdrawal = Withdrawal {
+            link_id: link_id.to_string(),
+            amount_msat,
+            created_at: now,
+        };
+        state.withdrawals.insert(k1.to_string(), withdrawal);
+        Ok(())
+    }
+
+    async fn cancel_withdrawal(&self, k1: &str) -> StorageResult<()> {
+        self.state.lock().unwrap().withdrawals.remove(k1);
+        Ok(())
+    }
+
+    async fn queue_payout(&self, k1: &str, bolt11: &str, amount_msat: u64, link: Option<&str>) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let payout = Payout::queued(k1, bolt11, amount_msat, link);
+        if state.payouts.insert(k1.to_string(), payout).is_none() {
+            state.payout_order.push(k1.to_string());
+        }
+        Ok(())
+    }
+
+    async fn claim_payouts(&self, limit: usize) -> StorageResult<Vec<Payout>> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let now = now();
+
+        let mut claimed = Vec::new();
+        for k1 in &state.payout_order {
+            if claimed.len() >= limit {
+                break;
+            }
+            let payout = state.payouts.get_mut(k1).expect("ordered payouts exist");
+            if payout.status == PayoutStatus::Queued && payout.next_attempt_at <= now {
+                payout.status = PayoutStatus::Paying;
+                payout.attempts += 1;
+                claimed.push(payout.clone());
+            }
+        }
+        Ok(claimed)
+    }
+
+    async fn finish_payout(&self, k1: &str, result: &PayoutResult) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        let payout = state.payouts.get_mut(k1).ok_or(K1Error::Unknown)?;
+        payout.finish(result);
+        Ok(())
+    }
+
+    async fn requeue_payouts(&self) -> StorageResult<usize> {
+        let mut state = self.state.lock().unwrap();
+        let mut requeued = 0;
+        for payout in state.payouts.values_mut() {
+            if payout.status == PayoutStatus::Paying {
+                payout.status = PayoutStatus::Queued;
+                requeued += 1;
+            }
+        }
+        Ok(requeued)
+    }
+
+    async fn get_payout(&self, k1: &str) -> StorageResult<Option<Payout>> {
+        Ok(self.state.lock().unwrap().payouts.get(k1).cloned())
+    }
+
+    async fn record_channel(&self, channel: &OpenedChannel) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.channels.retain(|c| c.k1 != channel.k1);
+        state.channels.push(channel.clone());
+        Ok(())
+    }
+
+    async fn update_channel(&self, channel_id: &str, new_state: ChannelState, short_channel_id: Option<&str>)
+        -> StorageResult<()>
+    {
+        let mut state = self.state.lock().unwrap();
+        if let Some(channel) = state.channels.iter_mut().find(|c| c.channel_id == channel_id) {
+            channel.state = new_state;
+            channel.short_channel_id = short_channel_id.map(str::to_string).or(channel.short_channel_id.take());
+            channel.updated_at = now();
+        }
+        Ok(())
+    }
+
+    async fn open_channels(&self) -> StorageResult<Vec<OpenedChannel>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.channels.iter().filter(|c| c.state != ChannelState::Closed).cloned().collect())
+    }
+
+    async fn get_channel(&self, k1: &str) -> StorageResult<Option<OpenedChannel>> {
+        Ok(self.state.lock().unwrap().channels.iter().find(|c| c.k1 == k1).cloned())
+    }
+
+    async fn channels_of(&self, remote_id: &str) -> StorageResult<Vec<OpenedChannel>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.channels.iter().filter(|c| c.remote_id == remote_id).cloned().collect())
+    }
+
+    async fn channels_opened_since(&self, since: u64) -> StorageResult<Vec<OpenedChannel>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.channels.iter().filter(|c| c.opened_at >= since).cloned().collect())
+    }
+
+    async fn create_session(&self, session: &AuthSession) -> StorageResult<()> {
+        self.state.lock().unwrap().sessions.insert(session.id.clone(), session.clone());
+        Ok(())
+    }
+
+    async fn get_session(&self, id: &str) -> StorageResult<Option<AuthSession>> {
+        Ok(self.state.lock().unwrap().sessions.get(id).cloned())
+    }
+
+    async fn session_of_k1(&self, k1: &str) -> StorageResult<Option<AuthSession>> {
+        Ok(self.state.lock().unwrap().sessions.values().find(|s| s.k1 == k1).cloned())
+    }
+
+    async fn revoke_session(&self, id: &str) -> StorageResult<()> {
+        if let Some(session) = self.state.lock().unwrap().sessions.get_mut(id) {
+            session.revoked = true;
+        }
+        Ok(())
+    }
+
+    async fn session_secret(&self) -> StorageResult<String> {
+        Ok(self.session_secret.clone())
+    }
+}
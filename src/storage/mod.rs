@@ -0,0 +1,193 @@
+//! Persistence of issued k1s and of the outcome of their callbacks
+//!
+//! Handlers only talk to a `Storage`, either kept in memory (lost on restart)
+//! or in SQLite so used-once guarantees survive a restart.
+//!
+//! Every k1 expires after the TTL of the protocol that issued it. Expired
+//! k1s are still reported as expired until `sweep` drops them, and once the
+//! store is full the oldest k1s are evicted to make room.
+//!
+//! Withdrawals through a withdraw link are reserved against it before paying,
+//! so concurrent callbacks of the same link cannot overspend it.
+//!
+//! Accepted withdraw invoices are queued as payouts, claimed by the payout
+//! worker and kept once final so wallets can query how they ended.
+//!
+//! Channels opened through LNURL-channel are kept with their latest state.
+//!
+//! Sessions opened by LNURL-auth are kept until they expire, `sweep` drops
+//! them along with the expired k1s.
+
+use async_trait::async_trait;
+use std::fmt;
+use std::time::Duration;
+
+use crate::backend::ChannelState;
+use crate::channels::OpenedChannel;
+use crate::k1::{K1Error, K1Kind, K1Outcome, K1Purpose, K1Record};
+use crate::payout::{Payout, PayoutResult, PayoutStatus};
+use crate::sessions::AuthSession;
+use crate::withdraw_links::{LinkError, LinkUsage, WithdrawLink};
+
+pub mod memory;
+pub mod sqlite;
+
+pub use memory::MemoryStorage;
+pub use sqlite::SqliteStorage;
+
+#[derive(Debug)]
+pub enum StorageError {
+    /// The k1 cannot be used by this callback
+    K1(K1Error),
+    /// The withdraw link cannot pay this amount now
+    Link(LinkError),
+    /// The database failed
+    Database(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::K1(e) => write!(f, "{}", e),
+            StorageError::Link(e) => write!(f, "{}", e),
+            StorageError::Database(e) => write!(f, "storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<K1Error> for StorageError {
+    fn from(e: K1Error) -> Self {
+        StorageError::K1(e)
+    }
+}
+
+impl From<LinkError> for StorageError {
+    fn from(e: LinkError) -> Self {
+        StorageError::Link(e)
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Track a freshly issued `k1` valid for `ttl`, evicting the oldest k1s when full
+    async fn insert_k1(&self, k1: &str, purpose: &K1Purpose, ttl: Duration) -> StorageResult<()>;
+
+    /// Atomically move a `kind` k1 from issued to in progress and return what it was
+    /// issued with, failing if it is unknown, issued for another flow, held by another
+    /// callback, already used or expired
+    async fn consume_k1(&self, k1: &str, kind: K1Kind) -> StorageResult<K1Purpose>;
+
+    /// Give an in-progress k1 back after a transient failure, so it can be retried
+    async fn release_k1(&self, k1: &str) -> StorageResult<()>;
+
+    /// Record how the callback holding `k1` ended, the k1 cannot be used again
+    async fn record_outcome(&self, k1: &str, outcome: &K1Outcome) -> StorageResult<()>;
+
+    async fn get_k1(&self, k1: &str) -> StorageResult<Option<K1Record>>;
+
+    /// Drop every expired k1 and session, returns how many were removed
+    async fn sweep(&self) -> StorageResult<usize>;
+
+    /// What the link `link_id` has been used for, pending withdrawals included
+    async fn link_usage(&self, link_id: &str) -> StorageResult<LinkUsage>;
+
+    /// Atomically check that `link` can pay `amount_msat` now and reserve it for the
+    /// callback holding `k1`, the reservation counts as a use until it is cancelled
+    async fn reserve_withdrawal(&self, link_id: &str, link: &WithdrawLink, k1: &str, amount_msat: u64)
+        -> StorageResult<()>;
+
+    /// Drop the reservation of `k1` once its payment failed, nothing was spent
+    async fn cancel_withdrawal(&self, k1: &str) -> StorageResult<()>;
+
+    /// Queue the payment of `bolt11` for the callback holding `k1`
+    async fn queue_payout(&self, k1: &str, bolt11: &str, amount_msat: u64, link: Option<&str>) -> StorageResult<()>;
+
+    /// Atomically move up to `limit` due payouts from queued to paying, counting the attempt
+    async fn claim_payouts(&self, limit: usize) -> StorageResult<Vec<Payout>>;
+
+    /// Record how an attempt at paying the payout of `k1` ended
+    async fn finish_payout(&self, k1: &str, result: &PayoutResult) -> StorageResult<()>;
+
+    /// Put payouts left paying by a previous run back in the queue, returns how many
+    async fn requeue_payouts(&self) -> StorageResult<usize>;
+
+    async fn get_payout(&self, k1: &str) -> StorageResult<Option<Payout>>;
+
+    /// Keep a channel funded for the callback holding its k1
+    async fn record_channel(&self, channel: &OpenedChannel) -> StorageResult<()>;
+
+    /// Record that the channel `channel_id` moved to `state`
+    async fn update_channel(&self, channel_id: &str, state: ChannelState, short_channel_id: Option<&str>)
+        -> StorageResult<()>;
+
+    /// Channels that are not closed yet, oldest first
+    async fn open_channels(&self) -> StorageResult<Vec<OpenedChannel>>;
+
+    /// The channel opened for `k1`
+    async fn get_channel(&self, k1: &str) -> StorageResult<Option<OpenedChannel>>;
+
+    /// Every channel opened to `remote_id`, oldest first
+    async fn channels_of(&self, remote_id: &str) -> StorageResult<Vec<OpenedChannel>>;
+
+    /// Channels opened at or after `since` (unix timestamp), oldest first
+    async fn channels_opened_since(&self, since: u64) -> StorageResult<Vec<OpenedChannel>>;
+
+    /// Keep a session opened by a login
+    async fn create_session(&self, session: &AuthSession) -> StorageResult<()>;
+
+    async fn get_session(&self, id: &str) -> StorageResult<Option<AuthSession>>;
+
+    /// The session opened by the login of `k1`
+    async fn session_of_k1(&self, k1: &str) -> StorageResult<Option<AuthSession>>;
+
+    /// Log out: the session stays revoked until it expires
+    async fn revoke_session(&self, id: &str) -> StorageResult<()>;
+
+    /// Secret signing session tokens when none is configured, generated once and
+    /// kept as long as the sessions it signs
+    async fn session_secret(&self) -> StorageResult<String>;
+}
+
+impl Payout {
+    fn queued(k1: &str, bolt11: &str, amount_msat: u64, link: Option<&str>) -> Self {
+        Payout {
+            k1: k1.to_string(),
+            bolt11: bolt11.to_string(),
+            amount_msat,
+            link: link.map(str::to_string),
+            status: PayoutStatus::Queued,
+            attempts: 0,
+            payment_hash: None,
+            fee_msat: None,
+            error: None,
+            next_attempt_at: now(),
+        }
+    }
+
+    fn finish(&mut self, result: &PayoutResult) {
+        match result {
+            PayoutResult::Paid { payment_hash, fee_msat } => {
+                self.status = PayoutStatus::Succeeded;
+                self.payment_hash = Some(payment_hash.clone());
+                self.fee_msat = Some(*fee_msat);
+            }
+            PayoutResult::Retry { error, at } => {
+                self.status = PayoutStatus::Queued;
+                self.error = Some(error.clone());
+                self.next_attempt_at = *at;
+            }
+            PayoutResult::Failed { error } => {
+                self.status = PayoutStatus::Failed;
+                self.error = Some(error.clone());
+            }
+        }
+    }
+}
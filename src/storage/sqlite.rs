@@ -0,0 +1,579 @@
+//! SQLite storage, k1s and their outcome survive a restart
+//!
+//! The schema is versioned with `PRAGMA user_version`: every entry of
+//! `MIGRATIONS` is applied once, in order, when the database is opened.
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::*;
+use crate::channels::ChannelLease;
+use crate::k1::K1Status;
+use crate::sessions::AuthSession;
+
+const MIGRATIONS: &[&str] = &[
+    // 1: issued k1s, `purpose` is the JSON `K1Purpose`
+    "CREATE TABLE k1 (
+        k1 TEXT PRIMARY KEY NOT NULL,
+        kind TEXT NOT NULL,
+        purpose TEXT NOT NULL,
+        status TEXT NOT NULL,
+        outcome TEXT,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+    CREATE INDEX k1_expires_at ON k1 (expires_at);",
+    // 2: withdrawals reserved against withdraw links, by k1
+    "CREATE TABLE link_withdrawal (
+        k1 TEXT PRIMARY KEY NOT NULL,
+        link_id TEXT NOT NULL,
+        amount_msat INTEGER NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX link_withdrawal_link_id ON link_withdrawal (link_id);",
+    // 3: withdraw payouts, paid in the background
+    "CREATE TABLE payout (
+        k1 TEXT PRIMARY KEY NOT NULL,
+        bolt11 TEXT NOT NULL,
+        amount_msat INTEGER NOT NULL,
+        link_id TEXT,
+        status TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        payment_hash TEXT,
+        error TEXT,
+        next_attempt_at INTEGER NOT NULL
+    );
+    CREATE INDEX payout_status ON payout (status, next_attempt_at);",
+    // 4: routing fees paid by each payout
+    "ALTER TABLE payout ADD COLUMN fee_msat INTEGER;",
+    // 5: channels opened through LNURL-channel, by k1
+    "CREATE TABLE channel (
+        k1 TEXT PRIMARY KEY NOT NULL,
+        remote_id TEXT NOT NULL,
+        channel_id TEXT NOT NULL,
+        txid TEXT NOT NULL,
+        outnum INTEGER NOT NULL,
+        capacity_sat INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        short_channel_id TEXT,
+        opened_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE INDEX channel_remote_id ON channel (remote_id);
+    CREATE INDEX channel_channel_id ON channel (channel_id);",
+    // 6: liquidity leased from the wallet with a channel
+    "ALTER TABLE channel ADD COLUMN lease_request_sat INTEGER;
+    ALTER TABLE channel ADD COLUMN lease_compact TEXT;
+    ALTER TABLE channel ADD COLUMN lease_fee_msat INTEGER;",
+    // 7: channels funded over a period, for the on-chain budget
+    "CREATE INDEX channel_opened_at ON channel (opened_at);",
+    // 8: sessions opened by LNURL-auth logins
+    "CREATE TABLE auth_session (
+        id TEXT PRIMARY KEY,
+        k1 TEXT NOT NULL UNIQUE,
+        linking_key TEXT NOT NULL,
+        secret_hash TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL,
+        revoked INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX auth_session_expires_at ON auth_session (expires_at);
+    CREATE TABLE session_secret (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        secret TEXT NOT NULL
+    );",
+];
+
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+    max_entries: usize,
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Database(e.to_string())
+    }
+}
+
+fn migrate(conn: &mut Connection) -> StorageResult<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(StorageError::Database(format!(
+            "database schema version {} is newer than this server ({})",
+            version,
+            MIGRATIONS.len()
+        )));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn read_record(purpose: String, status: String, outcome: Option<String>, expires_at: i64) -> StorageResult<K1Record> {
+    Ok(K1Record {
+        purpose: serde_json::from_str(&purpose).map_err(|e| StorageError::Database(e.to_string()))?,
+        status: status.parse().map_err(StorageError::Database)?,
+        outcome,
+        expires_at: expires_at as u64,
+    })
+}
+
+const PAYOUT_COLUMNS: &str =
+    "k1, bolt11, amount_msat, link_id, status, attempts, payment_hash, error, next_attempt_at, fee_msat";
+
+fn read_payout(row: &rusqlite::Row) -> rusqlite::Result<Payout> {
+    let status: String = row.get(4)?;
+    Ok(Payout {
+        k1: row.get(0)?,
+        bolt11: row.get(1)?,
+        amount_msat: row.get::<_, i64>(2)? as u64,
+        link: row.get(3)?,
+        status: status.parse().map_err(|e: String| {
+            rusqlite::Error::FromSqlConversionFailure(4, rusqlite::types::Type::Text, e.into())
+        })?,
+        attempts: row.get(5)?,
+        payment_hash: row.get(6)?,
+        error: row.get(7)?,
+        next_attempt_at: row.get::<_, i64>(8)? as u64,
+        fee_msat: row.get::<_, Option<i64>>(9)?.map(|fee| fee as u64),
+    })
+}
+
+const CHANNEL_COLUMNS: &str = "k1, remote_id, channel_id, txid, outnum, capacity_sat, state, short_channel_id, \
+    opened_at, updated_at, lease_request_sat, lease_compact, lease_fee_msat";
+
+const SESSION_COLUMNS: &str = "id, k1, linking_key, secret_hash, created_at, expires_at, revoked";
+
+fn read_session(row: &rusqlite::Row) -> rusqlite::Result<AuthSession> {
+    Ok(AuthSession {
+        id: row.get(0)?,
+        k1: row.get(1)?,
+        linking_key: row.get(2)?,
+        secret_hash: row.get(3)?,
+        created_at: row.get::<_, i64>(4)? as u64,
+        expires_at: row.get::<_, i64>(5)? as u64,
+        revoked: row.get(6)?,
+    })
+}
+
+fn read_channel(row: &rusqlite::Row) -> rusqlite::Result<OpenedChannel> {
+    let state: String = row.get(6)?;
+    Ok(OpenedChannel {
+        k1: row.get(0)?,
+        remote_id: row.get(1)?,
+        channel_id: row.get(2)?,
+        txid: row.get(3)?,
+        outnum: row.get(4)?,
+        capacity_sat: row.get::<_, i64>(5)? as u64,
+        state: state.parse().map_err(|e: String| {
+            rusqlite::Error::FromSqlConversionFailure(6, rusqlite::types::Type::Text, e.into())
+        })?,
+        short_channel_id: row.get(7)?,
+        opened_at: row.get::<_, i64>(8)? as u64,
+        updated_at: row.get::<_, i64>(9)? as u64,
+        lease: match row.get::<_, Option<String>>(11)? {
+            Some(compact_lease) => Some(ChannelLease {
+                request_sat: row.get::<_, i64>(10)? as u64,
+                compact_lease,
+                fee_msat: row.get::<_, i64>(12)? as u64,
+            }),
+            None => None,
+        },
+    })
+}
+
+impl SqliteStorage {
+    /// Open (or create) the database at `path` and bring its schema up to date
+    pub fn open(path: &Path, max_entries: usize) -> StorageResult<Self> {
+        Self::with_connection(Connection::open(path)?, max_entries)
+    }
+
+    /// A database that only lives as long as this value, for tests
+    pub fn open_in_memory(max_entries: usize) -> StorageResult<Self> {
+        Self::with_connection(Connection::open_in_memory()?, max_entries)
+    }
+
+    fn with_connection(mut conn: Connection, max_entries: usize) -> StorageResult<Self> {
+        migrate(&mut conn)?;
+        Ok(SqliteStorage {
+            conn: Mutex::new(conn),
+            max_entries: max_entries.max(1),
+        })
+    }
+
+    fn select(conn: &Connection, k1: &str) -> StorageResult<Option<K1Record>> {
+        let row = conn
+            .query_row(
+                "SELECT purpose, status, outcome, expires_at FROM k1 WHERE k1 = ?1",
+                params![k1],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()?;
+
+        row.map(|(purpose, status, outcome, expires_at)| read_record(purpose, status, outcome, expires_at))
+            .transpose()
+    }
+
+    fn link_usage(conn: &Connection, link_id: &str) -> StorageResult<LinkUsage> {
+        let (uses, spent_msat, last_used_at): (u32, i64, Option<i64>) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(amount_msat), 0), MAX(created_at)
+             FROM link_withdrawal WHERE link_id = ?1",
+            params![link_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+
+        Ok(LinkUsage {
+            uses,
+            spent_msat: spent_msat as u64,
+            last_used_at: last_used_at.map(|t| t as u64),
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn insert_k1(&self, k1: &str, purpose: &K1Purpose, ttl: Duration) -> StorageResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let now = now();
+
+        let count: usize = tx.query_row("SELECT COUNT(*) FROM k1", [], |row| row.get(0))?;
+        if count >= self.max_entries {
+            tx.execute(
+                "DELETE FROM k1 WHERE expires_at <= ?1 AND status != ?2",
+                params![now as i64, K1Status::InProgress.as_str()],
+            )?;
+            // rowids grow with insertion, the smallest ones are the oldest k1s. Those a callback holds are kept
+            tx.execute(
+                "DELETE FROM k1 WHERE rowid IN (
+                    SELECT rowid FROM k1 WHERE status != ?2 ORDER BY rowid
+                    LIMIT MAX(0, (SELECT COUNT(*) FROM k1) - ?1 + 1)
+                )",
+                params![self.max_entries as i64, K1Status::InProgress.as_str()],
+            )?;
+        }
+
+        let purpose_json = serde_json::to_string(purpose).map_err(|e| StorageError::Database(e.to_string()))?;
+        tx.execute(
+            "INSERT INTO k1 (k1, kind, purpose, status, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                k1,
+                purpose.kind().to_string(),
+                purpose_json,
+                K1Status::Issued.as_str(),
+                now as i64,
+                now.saturating_add(ttl.as_secs()) as i64,
+            ],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn consume_k1(&self, k1: &str, kind: K1Kind) -> StorageResult<K1Purpose> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let record = Self::select(&tx, k1)?.ok_or(K1Error::Unknown)?;
+        record.check_consumable(kind, now())?;
+
+        tx.execute(
+            "UPDATE k1 SET status = ?2 WHERE k1 = ?1",
+            params![k1, K1Status::InProgress.as_str()],
+        )?;
+        tx.commit()?;
+        Ok(record.purpose)
+    }
+
+    async fn release_k1(&self, k1: &str) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE k1 SET status = ?2 WHERE k1 = ?1 AND status = ?3",
+            params![k1, K1Status::Issued.as_str(), K1Status::InProgress.as_str()],
+        )?;
+        Ok(())
+    }
+
+    async fn record_outcome(&self, k1: &str, outcome: &K1Outcome) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE k1 SET status = ?2, outcome = ?3 WHERE k1 = ?1",
+            params![k1, outcome.status().as_str(), outcome.detail()],
+        )?;
+
+        if updated == 0 {
+            return Err(K1Error::Unknown.into());
+        }
+        Ok(())
+    }
+
+    async fn get_k1(&self, k1: &str) -> StorageResult<Option<K1Record>> {
+        let conn = self.conn.lock().unwrap();
+        Self::select(&conn, k1)
+    }
+
+    async fn sweep(&self) -> StorageResult<usize> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute(
+            "DELETE FROM k1 WHERE expires_at <= ?1 AND status != ?2",
+            params![now() as i64, K1Status::InProgress.as_str()],
+        )?;
+        let sessions = conn.execute("DELETE FROM auth_session WHERE expires_at <= ?1", params![now() as i64])?;
+        Ok(removed + sessions)
+    }
+
+    async fn link_usage(&self, link_id: &str) -> StorageResult<LinkUsage> {
+        let conn = self.conn.lock().unwrap();
+        Self::link_usage(&conn, link_id)
+    }
+
+    async fn reserve_withdrawal(&self, link_id: &str, link: &WithdrawLink, k1: &str, amount_msat: u64)
+        -> StorageResult<()>
+    {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let now = now();
+        link.check(&Self::link_usage(&tx, link_id)?, amount_msat, now)?;
+
+        tx.execute(
+            "INSERT OR REPLACE INTO link_withdrawal (k1, link_id, amount_msat, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![k1, link_id, amount_msat as i64, now as i64],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn cancel_withdrawal(&self, k1: &str) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM link_withdrawal WHERE k1 = ?1", params![k1])?;
+        Ok(())
+    }
+
+    async fn queue_payout(&self, k1: &str, bolt11: &str, amount_msat: u64, link: Option<&str>) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let payout = Payout::queued(k1, bolt11, amount_msat, link);
+        conn.execute(
+            &format!("INSERT OR REPLACE INTO payout ({PAYOUT_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"),
+            params![
+                payout.k1,
+                payout.bolt11,
+                payout.amount_msat as i64,
+                payout.link,
+                payout.status.as_str(),
+                payout.attempts,
+                payout.payment_hash,
+                payout.error,
+                payout.next_attempt_at as i64,
+                payout.fee_msat.map(|fee| fee as i64),
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn claim_payouts(&self, limit: usize) -> StorageResult<Vec<Payout>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        // rowids grow with queueing, oldest payouts first
+        let mut payouts = tx
+            .prepare(&format!(
+                "SELECT {PAYOUT_COLUMNS} FROM payout WHERE status = ?1 AND next_attempt_at <= ?2 ORDER BY rowid LIMIT ?3"
+            ))?
+            .query_map(
+                params![PayoutStatus::Queued.as_str(), now() as i64, limit as i64],
+                read_payout,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for payout in &mut payouts {
+            payout.status = PayoutStatus::Paying;
+            payout.attempts += 1;
+            tx.execute(
+                "UPDATE payout SET status = ?2, attempts = ?3 WHERE k1 = ?1",
+                params![payout.k1, payout.status.as_str(), payout.attempts],
+            )?;
+        }
+        tx.commit()?;
+        Ok(payouts)
+    }
+
+    async fn finish_payout(&self, k1: &str, result: &PayoutResult) -> StorageResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let mut payout = tx
+            .query_row(
+                &format!("SELECT {PAYOUT_COLUMNS} FROM payout WHERE k1 = ?1"),
+                params![k1],
+                read_payout,
+            )
+            .optional()?
+            .ok_or(K1Error::Unknown)?;
+        payout.finish(result);
+
+        tx.execute(
+            "UPDATE payout SET status = ?2, payment_hash = ?3, error = ?4, next_attempt_at = ?5, fee_msat = ?6
+             WHERE k1 = ?1",
+            params![
+                k1,
+                payout.status.as_str(),
+                payout.payment_hash,
+                payout.error,
+                payout.next_attempt_at as i64,
+                payout.fee_msat.map(|fee| fee as i64),
+            ],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn requeue_payouts(&self) -> StorageResult<usize> {
+        let conn = self.conn.lock().unwrap();
+        let requeued = conn.execute(
+            "UPDATE payout SET status = ?1 WHERE status = ?2",
+            params![PayoutStatus::Queued.as_str(), PayoutStatus::Paying.as_str()],
+        )?;
+        Ok(requeued)
+    }
+
+    async fn get_payout(&self, k1: &str) -> StorageResult<Option<Payout>> {
+        let conn = self.conn.lock().unwrap();
+        let payout = conn
+            .query_row(
+                &format!("SELECT {PAYOUT_COLUMNS} FROM payout WHERE k1 = ?1"),
+                params![k1],
+                read_payout,
+            )
+            .optional()?;
+        Ok(payout)
+    }
+
+    async fn record_channel(&self, channel: &OpenedChannel) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!(
+                "INSERT OR REPLACE INTO channel ({CHANNEL_COLUMNS})
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)"
+            ),
+            params![
+                channel.k1,
+                channel.remote_id,
+                channel.channel_id,
+                channel.txid,
+                channel.outnum,
+                channel.capacity_sat as i64,
+                channel.state.as_str(),
+                channel.short_channel_id,
+                channel.opened_at as i64,
+                channel.updated_at as i64,
+                channel.lease.as_ref().map(|lease| lease.request_sat as i64),
+                channel.lease.as_ref().map(|lease| &lease.compact_lease),
+                channel.lease.as_ref().map(|lease| lease.fee_msat as i64),
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn update_channel(&self, channel_id: &str, state: ChannelState, short_channel_id: Option<&str>)
+        -> StorageResult<()>
+    {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE channel SET state = ?2, short_channel_id = COALESCE(?3, short_channel_id), updated_at = ?4
+             WHERE channel_id = ?1",
+            params![channel_id, state.as_str(), short_channel_id, now() as i64],
+        )?;
+        Ok(())
+    }
+
+    async fn open_channels(&self) -> StorageResult<Vec<OpenedChannel>> {
+        let conn = self.conn.lock().unwrap();
+        let channels = conn
+            .prepare(&format!("SELECT {CHANNEL_COLUMNS} FROM channel WHERE state != ?1 ORDER BY rowid"))?
+            .query_map(params![ChannelState::Closed.as_str()], read_channel)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(channels)
+    }
+
+    async fn get_channel(&self, k1: &str) -> StorageResult<Option<OpenedChannel>> {
+        let conn = self.conn.lock().unwrap();
+        let channel = conn
+            .query_row(
+                &format!("SELECT {CHANNEL_COLUMNS} FROM channel WHERE k1 = ?1"),
+                params![k1],
+                read_channel,
+            )
+            .optional()?;
+        Ok(channel)
+    }
+
+    async fn channels_of(&self, remote_id: &str) -> StorageResult<Vec<OpenedChannel>> {
+        let conn = self.conn.lock().unwrap();
+        let channels = conn
+            .prepare(&format!("SELECT {CHANNEL_COLUMNS} FROM channel WHERE remote_id = ?1 ORDER BY rowid"))?
+            .query_map(params![remote_id], read_channel)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(channels)
+    }
+
+    async fn channels_opened_since(&self, since: u64) -> StorageResult<Vec<OpenedChannel>> {
+        let conn = self.conn.lock().unwrap();
+        let channels = conn
+            .prepare(&format!("SELECT {CHANNEL_COLUMNS} FROM channel WHERE opened_at >= ?1 ORDER BY rowid"))?
+            .query_map(params![since as i64], read_channel)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(channels)
+    }
+
+    async fn create_session(&self, session: &AuthSession) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!("INSERT INTO auth_session ({SESSION_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"),
+            params![
+                session.id,
+                session.k1,
+                session.linking_key,
+                session.secret_hash,
+                session.created_at as i64,
+                session.expires_at as i64,
+                session.revoked,
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn get_session(&self, id: &str) -> StorageResult<Option<AuthSession>> {
+        let conn = self.conn.lock().unwrap();
+        let session = conn
+            .query_row(&format!("SELECT {SESSION_COLUMNS} FROM auth_session WHERE id = ?1"), params![id], read_session)
+            .optional()?;
+        Ok(session)
+    }
+
+    async fn session_of_k1(&self, k1: &str) -> StorageResult<Option<AuthSession>> {
+        let conn = self.conn.lock().unwrap();
+        let session = conn
+            .query_row(&format!("SELECT {SESSION_COLUMNS} FROM auth_session WHERE k1 = ?1"), params![k1], read_session)
+            .optional()?;
+        Ok(session)
+    }
+
+    async fn revoke_session(&self, id: &str) -> StorageResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("UPDATE auth_session SET revoked = 1 WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    async fn session_secret(&self) -> StorageResult<String> {
+        let conn = self.conn.lock().unwrap();
+        let secret = hex::encode(rand::random::<[u8; 32]>());
+        conn.execute("INSERT OR IGNORE INTO session_secret (id, secret) VALUES (0, ?1)", params![secret])?;
+        Ok(conn.query_row("SELECT secret FROM session_secret WHERE id = 0", [], |row| row.get(0))?)
+    }
+}
@@ -0,0 +1,152 @@
+//! Operator-managed LNURL-withdraw links
+//!
+//! Each link has a total budget, per-use bounds, a number of uses, an
+//! optional validity window and an optional wait between uses. Links are read
+//! at startup from a JSON registry keyed by link id; what they spent is kept
+//! by the `crate::storage::Storage` so it survives restarts.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::config::FeePolicy;
+use crate::is_valid_username;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WithdrawLink {
+    pub description: String,
+    pub budget_msat: u64, // total that can be withdrawn through the link
+    pub min_withdrawable: u64, // per use, in millisatoshis
+    pub max_withdrawable: u64, // per use, in millisatoshis
+    pub uses: u32,
+    #[serde(default)]
+    pub valid_from: Option<u64>, // unix timestamp, in seconds
+    #[serde(default)]
+    pub valid_until: Option<u64>, // unix timestamp, in seconds
+    #[serde(default)]
+    pub wait_secs: u64, // minimum time between two uses
+    #[serde(default)]
+    pub fees: FeePolicy, // over the `[payout.fees]` defaults
+}
+
+/// What a link has been used for so far, reserved withdrawals included
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LinkUsage {
+    pub uses: u32,
+    pub spent_msat: u64,
+    pub last_used_at: Option<u64>, // unix timestamp, in seconds
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkError {
+    NotStarted,
+    Ended,
+    UsesExhausted,
+    BudgetExhausted { remaining_msat: u64 },
+    TooSoon { retry_in_secs: u64 },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::NotStarted => write!(f, "this link is not active yet"),
+            LinkError::Ended => write!(f, "this link has ended"),
+            LinkError::UsesExhausted => write!(f, "this link has no uses left"),
+            LinkError::BudgetExhausted { remaining_msat } => {
+                write!(f, "only {} msat are left on this link", remaining_msat)
+            }
+            LinkError::TooSoon { retry_in_secs } => {
+                write!(f, "the next withdrawal is possible in {} seconds", retry_in_secs)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+impl LinkError {
+    /// Whether no later withdrawal through the link can succeed
+    pub fn is_final(&self) -> bool {
+        matches!(self, LinkError::Ended | LinkError::UsesExhausted)
+    }
+}
+
+impl WithdrawLink {
+    /// Per-use bounds the link can offer at `now`, given its `usage`
+    pub fn available(&self, usage: &LinkUsage, now: u64) -> Result<(u64, u64), LinkError> {
+        if self.valid_from.is_some_and(|from| now < from) {
+            return Err(LinkError::NotStarted);
+        }
+        if self.valid_until.is_some_and(|until| now >= until) {
+            return Err(LinkError::Ended);
+        }
+        if usage.uses >= self.uses {
+            return Err(LinkError::UsesExhausted);
+        }
+
+        let remaining_msat = self.budget_msat.saturating_sub(usage.spent_msat);
+        if remaining_msat < self.min_withdrawable {
+            return Err(LinkError::BudgetExhausted { remaining_msat });
+        }
+
+        if let Some(last) = usage.last_used_at {
+            let next = last.saturating_add(self.wait_secs);
+            if now < next {
+                return Err(LinkError::TooSoon {
+                    retry_in_secs: next - now,
+                });
+            }
+        }
+
+        Ok((self.min_withdrawable, self.max_withdrawable.min(remaining_msat)))
+    }
+
+    /// Check that `amount_msat` can be withdrawn through the link at `now`
+    pub fn check(&self, usage: &LinkUsage, amount_msat: u64, now: u64) -> Result<(), LinkError> {
+        let (_, max) = self.available(usage, now)?;
+        if amount_msat > max {
+            return Err(LinkError::BudgetExhausted {
+                remaining_msat: self.budget_msat.saturating_sub(usage.spent_msat),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Load the withdraw link registry, an absent file means no links
+pub fn load_withdraw_links(path: &std::path::Path) -> Result<HashMap<String, WithdrawLink>, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(format!("cannot read {}: {e}", path.display())),
+    };
+    let path = path.display();
+
+    let links: HashMap<String, WithdrawLink> =
+        serde_json::from_str(&content).map_err(|e| format!("invalid {path}: {e}"))?;
+
+    for (id, link) in &links {
+        // Ids end up in URLs, same charset as Lightning Address usernames
+        if !is_valid_username(id) {
+            return Err(format!("invalid link id {id:?} in {path}"));
+        }
+        if link.min_withdrawable == 0 || link.min_withdrawable > link.max_withdrawable {
+            return Err(format!("expected 0 < min_withdrawable <= max_withdrawable for {id} in {path}"));
+        }
+        if link.max_withdrawable > link.budget_msat {
+            return Err(format!("max_withdrawable > budget_msat for {id} in {path}"));
+        }
+        if link.uses == 0 {
+            return Err(format!("uses must be positive for {id} in {path}"));
+        }
+        link.fees.validate().map_err(|e| format!("invalid fees for {id} in {path}: {e}"))?;
+        if let (Some(from), Some(until)) = (link.valid_from, link.valid_until) {
+            if from >= until {
+                return Err(format!("valid_from >= valid_until for {id} in {path}"));
+            }
+        }
+    }
+
+    Ok(links)
+}
@@ -0,0 +1,1655 @@
+//! End-to-end LNURL flows against the service router backed by mock nodes
+
+use lnurl_project::backend::mock::MOCK_INVOICE_PREFIX;
+use lnurl_project::backend::{
+    BackendError, ChannelState, ChannelType, InvoiceParams, LeaseRates, LightningBackend, MockBackend, MockCall,
+    OnchainBalance, PaymentStatus,
+};
+use lnurl_project::config::{
+    Capacity, ChannelOffer, ChannelPolicy, FeePolicy, K1Settings, LeaseTerms, PayoutSettings, WithdrawLimits,
+};
+use lnurl_project::k1::K1Kind;
+use lnurl_project::service::{self, AppState, LightningAddressUser, ServiceConfig};
+use lnurl_project::storage::{MemoryStorage, Storage};
+use lnurl_project::withdraw_links::WithdrawLink;
+use lnurl_project::*;
+use reqwest::{Client, StatusCode, Url};
+use secp256k1::SecretKey;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+struct TestService {
+    base_url: String,
+    node: Arc<MockBackend>,
+    storage: Arc<MemoryStorage>,
+    http: Client,
+}
+
+async fn spawn_service() -> TestService {
+    spawn_service_with(HashMap::new()).await
+}
+
+async fn spawn_service_with(users: HashMap<String, LightningAddressUser>) -> TestService {
+    spawn_service_with_config(users, Default::default()).await
+}
+
+async fn spawn_service_with_config(users: HashMap<String, LightningAddressUser>, k1: K1Settings) -> TestService {
+    let setup = Setup {
+        users,
+        k1,
+        ..Default::default()
+    };
+    spawn_service_full(MockBackend::new(1), setup).await
+}
+
+async fn spawn_service_with_links(withdraw: WithdrawLimits, links: HashMap<String, WithdrawLink>) -> TestService {
+    let setup = Setup {
+        withdraw,
+        withdraw_links: links,
+        ..Default::default()
+    };
+    spawn_service_full(MockBackend::new(1), setup).await
+}
+
+/// Everything a test may configure, the defaults otherwise
+struct Setup {
+    users: HashMap<String, LightningAddressUser>,
+    k1: K1Settings,
+    channel: ChannelOffer,
+    channel_offers: HashMap<String, ChannelOffer>,
+    channel_policy: ChannelPolicy,
+    withdraw: WithdrawLimits,
+    withdraw_links: HashMap<String, WithdrawLink>,
+    internal_api_token: Option<String>,
+    payout: PayoutSettings,
+    session_ttl_secs: u64,
+}
+
+impl Default for Setup {
+    /// The open `/withdraw-request` of a test node, closed by default
+    fn default() -> Self {
+        Setup {
+            users: Default::default(),
+            k1: Default::default(),
+            channel: Default::default(),
+            channel_offers: Default::default(),
+            channel_policy: Default::default(),
+            withdraw: WithdrawLimits {
+                open: true,
+                ..Default::default()
+            },
+            withdraw_links: Default::default(),
+            internal_api_token: None,
+            payout: PayoutSettings {
+                retry_secs: 0,
+                pending_poll_secs: 0,
+                ..Default::default()
+            },
+            session_ttl_secs: 3600,
+        }
+    }
+}
+
+async fn spawn_service_full(node: MockBackend, setup: Setup) -> TestService {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+    let node = Arc::new(node);
+    let config = ServiceConfig {
+        server_url: base_url.clone(),
+        node_uri: format!("{}@127.0.0.1:9735", node.node_id()),
+        ln_address_domain: "example.com".to_string(),
+        network: "testnet4".to_string(),
+        legacy_zbase_auth: false,
+        internal_api_token: setup.internal_api_token,
+        session_secret: SESSION_SECRET.to_string(),
+        session_ttl_secs: setup.session_ttl_secs,
+        k1: setup.k1,
+        channel: setup.channel,
+        channel_offers: setup.channel_offers,
+        channel_policy: setup.channel_policy,
+        withdraw: setup.withdraw,
+        pay: Default::default(),
+    };
+
+    let storage = Arc::new(MemoryStorage::new(config.k1.max_entries));
+    let state = AppState::new(node.clone(), storage.clone(), config, setup.users);
+    let state = state.with_withdraw_links(setup.withdraw_links);
+    service::spawn_payout_worker(state.clone(), setup.payout);
+    service::spawn_channel_watcher(state.clone(), std::time::Duration::from_millis(50));
+    let app = service::router(state);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    TestService {
+        base_url,
+        node,
+        storage,
+        http: Client::new(),
+    }
+}
+
+impl TestService {
+    /// Fetch the LNURL of an endpoint and follow it, as a wallet scanning the QR would
+    async fn scan<T: serde::de::DeserializeOwned>(&self, endpoint: &str) -> T {
+        let link: LnurlResponse = self
+            .http
+            .get(format!("{}/lnurl/{}", self.base_url, endpoint))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let url = decode_lnurl(&link.lnurl).unwrap();
+        self.http.get(url).send().await.unwrap().json().await.unwrap()
+    }
+
+    /// A wallet node that connected to the service node, as LUD-02 asks
+    fn connected_wallet(&self, seed: u8) -> MockBackend {
+        let wallet = MockBackend::new(seed);
+        self.node.add_peer(&wallet.node_id());
+        wallet
+    }
+
+    async fn get(&self, url: Url) -> reqwest::Response {
+        self.http.get(url).send().await.unwrap()
+    }
+
+    /// Wait for the channel opened for `k1` to reach `state`
+    async fn channel_state(&self, k1: &str, state: &str) -> ChannelStatusResponse {
+        let url = Url::parse(&format!("{}/channel-status/{}", self.base_url, k1)).unwrap();
+        for _ in 0..100 {
+            let status: ChannelStatusResponse = self.get(url.clone()).await.json().await.unwrap();
+            if status.state == state {
+                return status;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("channel of {} never became {}", k1, state);
+    }
+
+    /// Wait for the background payout of a withdraw k1 to end
+    async fn payout(&self, k1: &str) -> WithdrawStatusResponse {
+        let url = Url::parse(&format!("{}/withdraw-status/{}", self.base_url, k1)).unwrap();
+        for _ in 0..100 {
+            let status: WithdrawStatusResponse = self.get(url.clone()).await.json().await.unwrap();
+            if matches!(status.status.as_str(), "succeeded" | "failed") {
+                return status;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("payout of {} did not end", k1);
+    }
+
+    /// Current state of the payout of a withdraw k1, without waiting for it to end
+    async fn payout_now(&self, k1: &str) -> WithdrawStatusResponse {
+        let url = Url::parse(&format!("{}/withdraw-status/{}", self.base_url, k1)).unwrap();
+        self.get(url).await.json().await.unwrap()
+    }
+}
+
+fn callback(base: &str, params: &[(&str, &str)]) -> Url {
+    Url::parse_with_params(base, params).unwrap()
+}
+
+/// Failures are LUD `{"status":"ERROR","reason":...}` bodies served with 200
+async fn error_reason(resp: reqwest::Response) -> String {
+    assert_eq!(resp.status(), StatusCode::OK);
+    let error: ErrorResponse = resp.json().await.unwrap();
+    assert_eq!(error.status, "ERROR");
+    error.reason
+}
+
+// ============================================================================
+// LUD-02: Channel Request
+// ============================================================================
+
+#[tokio::test]
+async fn channel_request_opens_channel() {
+    let service = spawn_service().await;
+    let wallet = service.connected_wallet(2);
+
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    assert_eq!(req.tag, CHANNEL_REQUEST_TAG);
+    assert!(req.uri.starts_with(&service.node.node_id().to_string()));
+
+    let remote_id = wallet.node_id().to_string();
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("private", "1")]);
+    let resp = service.get(url).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp: OpenChannelResponse = resp.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    let channels = service.node.channels();
+    assert_eq!(channels.len(), 1);
+    assert_eq!(channels[0].node_id, wallet.node_id());
+    assert!(!channels[0].announce);
+}
+
+#[tokio::test]
+async fn channel_request_rejects_reused_k1() {
+    let service = spawn_service().await;
+    let remote_id = service.connected_wallet(2).node_id().to_string();
+
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("private", "0")]);
+
+    assert_eq!(service.get(url.clone()).await.status(), StatusCode::OK);
+    let reason = error_reason(service.get(url).await).await;
+    assert_eq!(reason, ServiceError::UsedK1.to_string());
+    assert_eq!(service.node.channels().len(), 1);
+}
+
+#[tokio::test]
+async fn channel_request_reports_funding_failure() {
+    let service = spawn_service().await;
+    let remote_id = service.connected_wallet(2).node_id().to_string();
+    service.node.fail(MockCall::FundChannel, "not enough funds");
+
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("private", "0")]);
+
+    let reason = error_reason(service.get(url).await).await;
+    assert!(reason.contains("not enough funds"), "{}", reason);
+    assert!(service.node.channels().is_empty());
+}
+
+#[tokio::test]
+async fn channel_request_rejects_invalid_remote_id() {
+    let service = spawn_service().await;
+
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", "not-a-node"), ("private", "0")]);
+
+    let reason = error_reason(service.get(url).await).await;
+    assert_eq!(reason, ServiceError::InvalidRemoteId.to_string());
+}
+
+#[tokio::test]
+async fn channel_request_waits_for_the_wallet_to_connect() {
+    let setup = Setup {
+        channel_policy: ChannelPolicy {
+            connect_grace_secs: 0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let wallet = MockBackend::new(2);
+    let remote_id = wallet.node_id().to_string();
+
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("private", "0")]);
+
+    let reason = error_reason(service.get(url.clone()).await).await;
+    let expected = ServiceError::TryAgain(Box::new(ServiceError::PeerNotConnected(remote_id.clone())));
+    assert_eq!(reason, expected.to_string());
+    assert!(service.node.channels().is_empty());
+
+    // The k1 was released, once connected the same callback opens the channel
+    service.node.add_peer(&wallet.node_id());
+    let resp: OpenChannelResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+    assert_eq!(service.node.channels().len(), 1);
+}
+
+#[tokio::test]
+async fn channel_request_gives_the_wallet_a_grace_period() {
+    let service = spawn_service().await;
+    let wallet = MockBackend::new(2);
+    let remote_id = wallet.node_id().to_string();
+
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("private", "0")]);
+
+    // The wallet connects while the callback is waiting
+    let node = service.node.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        node.add_peer(&wallet.node_id());
+    });
+
+    let resp: OpenChannelResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+    assert_eq!(service.node.channels().len(), 1);
+}
+
+#[tokio::test]
+async fn channel_request_connects_to_the_wallet_host_when_allowed() {
+    let policy = |outbound_connect| ChannelPolicy {
+        connect_grace_secs: 0,
+        outbound_connect,
+        ..Default::default()
+    };
+    let remote_id = MockBackend::new(2).node_id().to_string();
+    let params = |k1: &str, host: &str| {
+        let params = [("k1", k1), ("remoteid", &remote_id), ("private", "0"), ("host", host), ("port", "9735")];
+        params.map(|(k, v)| (k.to_string(), v.to_string()))
+    };
+
+    // Opt-in: the host is ignored by default
+    let setup = Setup {
+        channel_policy: policy(false),
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = Url::parse_with_params(&req.callback, params(&req.k1, "1.1.1.1")).unwrap();
+    let reason = error_reason(service.get(url).await).await;
+    assert!(reason.contains("is not connected"), "{}", reason);
+    assert!(service.node.peers().is_empty());
+
+    let setup = Setup {
+        channel_policy: policy(true),
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+
+    // Never towards our own networks
+    for host in ["127.0.0.1", "10.0.0.2", "169.254.169.254", "::1", "localhost"] {
+        let url = Url::parse_with_params(&req.callback, params(&req.k1, host)).unwrap();
+        let reason = error_reason(service.get(url).await).await;
+        assert!(reason.contains("is not a public address"), "{}: {}", host, reason);
+    }
+    assert!(service.node.peers().is_empty());
+
+    let url = Url::parse_with_params(&req.callback, params(&req.k1, "1.1.1.1")).unwrap();
+    service.node.fail_once(MockCall::ConnectPeer, "Connection refused");
+    let reason = error_reason(service.get(url.clone()).await).await;
+    assert!(reason.contains("Could not connect to your node"), "{}", reason);
+
+    let resp: OpenChannelResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+    assert_eq!(service.node.peers().len(), 1);
+    assert_eq!(service.node.channels().len(), 1);
+}
+
+#[tokio::test]
+async fn channel_request_can_be_cancelled() {
+    let service = spawn_service().await;
+    // Not connected: cancelling does not wait for the wallet
+    let remote_id = MockBackend::new(2).node_id().to_string();
+
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("cancel", "1")]);
+    let resp: OpenChannelResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("private", "0")]);
+    let reason = error_reason(service.get(url).await).await;
+    assert_eq!(reason, ServiceError::CancelledK1.to_string());
+    assert!(service.node.channels().is_empty());
+}
+
+#[tokio::test]
+async fn channel_status_follows_the_opening() {
+    let service = spawn_service().await;
+    let remote_id = service.connected_wallet(2).node_id().to_string();
+
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("private", "0")]);
+    assert_eq!(service.get(url).await.status(), StatusCode::OK);
+
+    let status = service.channel_state(&req.k1, "awaiting_lockin").await;
+    assert_eq!((status.remote_id.as_str(), status.capacity_sat), (remote_id.as_str(), 100_000));
+    assert!(status.short_channel_id.is_none());
+
+    service.node.set_channel_state(&status.channel_id, ChannelState::Normal);
+    let status = service.channel_state(&req.k1, "normal").await;
+    assert!(status.short_channel_id.is_some());
+
+    service.node.set_channel_state(&status.channel_id, ChannelState::Closed);
+    service.channel_state(&req.k1, "closed").await;
+
+    let url = Url::parse(&format!("{}/channel-status/node/{}", service.base_url, remote_id)).unwrap();
+    let channels: Vec<ChannelStatusResponse> = service.get(url).await.json().await.unwrap();
+    assert_eq!(channels.len(), 1);
+    assert_eq!((channels[0].k1.as_deref(), channels[0].txid.as_str()), (None, status.txid.as_str()));
+
+    let url = Url::parse(&format!("{}/channel-status/{}", service.base_url, "00".repeat(32))).unwrap();
+    let reason = error_reason(service.get(url).await).await;
+    assert_eq!(reason, ServiceError::UnknownChannel.to_string());
+}
+
+fn big_offer() -> HashMap<String, ChannelOffer> {
+    let offer = ChannelOffer {
+        capacity: Capacity::Range {
+            min: 200_000,
+            max: 500_000,
+        },
+        push_msat: 10_000_000,
+        feerate: Some("urgent".to_string()),
+        channel_type: ChannelType::ZeroConf,
+        minconf: Some(2),
+        reserve_sat: Some(5_000),
+        allow_private: false,
+        lease: None,
+    };
+    HashMap::from([("big".to_string(), offer)])
+}
+
+#[tokio::test]
+async fn channel_offer_funds_on_its_terms() {
+    let setup = Setup {
+        channel_offers: big_offer(),
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let remote_id = service.connected_wallet(2).node_id().to_string();
+
+    let req: ChannelRequestResponse = service.scan("channel-request/big").await;
+    let params = [("k1", req.k1.as_str()), ("remoteid", &remote_id), ("private", "0"), ("capacity", "300000")];
+    let resp: OpenChannelResponse = service.get(callback(&req.callback, &params)).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    let channel = &service.node.channels()[0];
+    assert_eq!((channel.amount_sat, channel.push_msat), (300_000, 10_000_000));
+    assert_eq!((channel.feerate.as_deref(), channel.channel_type), (Some("urgent"), ChannelType::ZeroConf));
+    assert_eq!((channel.minconf, channel.reserve_sat), (Some(2), Some(5_000)));
+    assert!(channel.announce);
+
+    // The default offer is untouched
+    let remote_id = service.connected_wallet(3).node_id().to_string();
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("private", "1")]);
+    assert_eq!(service.get(url).await.status(), StatusCode::OK);
+    let channel = &service.node.channels()[1];
+    assert_eq!((channel.amount_sat, channel.push_msat, channel.announce), (100_000, 0, false));
+}
+
+#[tokio::test]
+async fn channel_offer_refuses_requests_outside_its_terms() {
+    let setup = Setup {
+        channel_offers: big_offer(),
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let remote_id = service.connected_wallet(2).node_id().to_string();
+
+    let req: ChannelRequestResponse = service.scan("channel-request/big").await;
+    let rejected = [
+        (vec![("private", "1")], "public channels"),
+        (vec![("private", "0"), ("capacity", "100")], "between 200000 and 500000 sat"),
+    ];
+    for (extra, expected) in rejected {
+        let mut params = vec![("k1", req.k1.as_str()), ("remoteid", &remote_id)];
+        params.extend(extra);
+        let reason = error_reason(service.get(callback(&req.callback, &params)).await).await;
+        assert!(reason.contains(expected), "{} does not mention {}", reason, expected);
+    }
+    assert!(service.node.channels().is_empty());
+
+    // The k1 is still good, without a capacity the smallest channel is opened
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("private", "0")]);
+    assert_eq!(service.get(url).await.status(), StatusCode::OK);
+    assert_eq!(service.node.channels()[0].amount_sat, 200_000);
+
+    let url = Url::parse(&format!("{}/lnurl/channel-request/small", service.base_url)).unwrap();
+    let reason = error_reason(service.get(url).await).await;
+    assert_eq!(reason, ServiceError::UnknownChannelOffer("small".to_string()).to_string());
+}
+
+#[tokio::test]
+async fn channel_offer_leases_inbound_liquidity() {
+    let terms = LeaseTerms {
+        request_sat: 500_000,
+        max_fee_msat: 6_000_000,
+    };
+    let offer = ChannelOffer {
+        lease: Some(terms.clone()),
+        ..Default::default()
+    };
+    let setup = Setup {
+        channel_offers: HashMap::from([("inbound".to_string(), offer)]),
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let wallet = service.connected_wallet(2);
+    let remote_id = wallet.node_id().to_string();
+
+    let req: ChannelRequestResponse = service.scan("channel-request/inbound").await;
+    let wire = lnurl_project::LeaseTerms {
+        request_sat: terms.request_sat,
+        max_fee_msat: terms.max_fee_msat,
+    };
+    assert_eq!(req.lease, Some(wire));
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("private", "0")]);
+
+    let reason = error_reason(service.get(url.clone()).await).await;
+    assert!(reason.contains("advertises none"), "{}", reason);
+
+    // 1000 sat + 2% of 500000 sat is more than the offer pays, 1000 sat + 1% is just enough
+    let rates = |lease_fee_basis| LeaseRates {
+        compact_lease: format!("lease-{}", lease_fee_basis),
+        lease_fee_base_msat: 1_000_000,
+        lease_fee_basis,
+        funding_weight: 666,
+        channel_fee_max_base_msat: 5_000,
+        channel_fee_max_proportional_thousandths: 1,
+    };
+    service.node.set_lease_rates(&wallet.node_id(), rates(200));
+    let reason = error_reason(service.get(url.clone()).await).await;
+    assert!(reason.contains("asks 11000000 msat"), "{}", reason);
+    assert!(service.node.channels().is_empty());
+
+    service.node.set_lease_rates(&wallet.node_id(), rates(100));
+    let resp: OpenChannelResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+    let channel = &service.node.channels()[0];
+    assert_eq!(channel.request_amt_sat, Some(500_000));
+    assert_eq!(channel.compact_lease.as_deref(), Some("lease-100"));
+
+    let status = service.channel_state(&req.k1, "awaiting_lockin").await;
+    let lease = status.lease.unwrap();
+    assert_eq!((lease.request_sat, lease.fee_msat), (500_000, 6_000_000));
+}
+
+/// Ask the default offer for a channel to `remote_id`, with a fresh k1
+async fn request_channel(service: &TestService, remote_id: &str) -> reqwest::Response {
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", remote_id), ("private", "0")]);
+    service.get(url).await
+}
+
+#[tokio::test]
+async fn channel_policy_limits_channels_per_node() {
+    let policy = ChannelPolicy {
+        max_channels_per_node: 1,
+        min_open_interval_secs: 0,
+        ..Default::default()
+    };
+    let setup = Setup {
+        channel_policy: policy,
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let remote_id = service.connected_wallet(2).node_id().to_string();
+
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &remote_id), ("private", "0")]);
+    assert_eq!(service.get(url).await.status(), StatusCode::OK);
+    let reason = error_reason(request_channel(&service, &remote_id).await).await;
+    assert!(reason.contains("already has 1 channel(s) with us, the limit is 1"), "{}", reason);
+
+    // Once closed, the channel no longer counts
+    let status = service.channel_state(&req.k1, "awaiting_lockin").await;
+    service.node.set_channel_state(&status.channel_id, ChannelState::Closed);
+    service.channel_state(&req.k1, "closed").await;
+    let resp: OpenChannelResponse = request_channel(&service, &remote_id).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+    assert_eq!(service.node.channels().len(), 2);
+}
+
+#[tokio::test]
+async fn channel_policy_spaces_opens_to_a_node() {
+    let policy = ChannelPolicy {
+        max_channels_per_node: 0,
+        min_open_interval_secs: 3600,
+        ..Default::default()
+    };
+    let setup = Setup {
+        channel_policy: policy,
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let remote_id = service.connected_wallet(2).node_id().to_string();
+
+    assert_eq!(request_channel(&service, &remote_id).await.status(), StatusCode::OK);
+    let reason = error_reason(request_channel(&service, &remote_id).await).await;
+    assert!(reason.contains("opened to your node recently, try again in"), "{}", reason);
+
+    // Other nodes are not held up
+    let other = service.connected_wallet(3).node_id().to_string();
+    let resp: OpenChannelResponse = request_channel(&service, &other).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+    assert_eq!(service.node.channels().len(), 2);
+}
+
+#[tokio::test]
+async fn channel_policy_allow_and_deny_lists() {
+    let (denied, allowed, unlisted) = (MockBackend::new(2), MockBackend::new(3), MockBackend::new(4));
+    let policy = ChannelPolicy {
+        allow: vec![allowed.node_id().to_string(), denied.node_id().to_string()],
+        deny: vec![denied.node_id().to_string()],
+        ..Default::default()
+    };
+    let setup = Setup {
+        channel_policy: policy,
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    for wallet in [&denied, &allowed, &unlisted] {
+        service.node.add_peer(&wallet.node_id());
+    }
+
+    let reason = error_reason(request_channel(&service, &denied.node_id().to_string()).await).await;
+    assert!(reason.contains("denied"), "{}", reason);
+    let reason = error_reason(request_channel(&service, &unlisted.node_id().to_string()).await).await;
+    assert!(reason.contains("not on the list"), "{}", reason);
+    assert!(service.node.channels().is_empty());
+
+    let resp = request_channel(&service, &allowed.node_id().to_string()).await;
+    let resp: OpenChannelResponse = resp.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+}
+
+#[tokio::test]
+async fn channel_policy_caps_onchain_spending() {
+    let policy = ChannelPolicy {
+        daily_budget_sat: Some(150_000),
+        onchain_reserve_sat: 10_000,
+        ..Default::default()
+    };
+    let setup = Setup {
+        channel_policy: policy,
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let first = service.connected_wallet(2).node_id().to_string();
+    let second = service.connected_wallet(3).node_id().to_string();
+
+    // 100000 sat of the 150000 sat budget are left once the first channel is funded
+    assert_eq!(request_channel(&service, &first).await.status(), StatusCode::OK);
+    let reason = error_reason(request_channel(&service, &second).await).await;
+    assert!(reason.contains("all the channels we can for today"), "{}", reason);
+    assert_eq!(service.node.channels().len(), 1);
+
+    // The funding must leave the reserve confirmed in the wallet, the k1 is kept meanwhile
+    let setup = Setup {
+        channel_policy: ChannelPolicy {
+            onchain_reserve_sat: 10_000,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    service.connected_wallet(2);
+    let unconfirmed = OnchainBalance {
+        confirmed_sat: 105_000,
+        unconfirmed_sat: 500_000,
+    };
+    service.node.set_onchain_balance(unconfirmed);
+    let req: ChannelRequestResponse = service.scan("channel-request").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("remoteid", &first), ("private", "0")]);
+    let reason = error_reason(service.get(url.clone()).await).await;
+    assert!(reason.contains("not enough confirmed on-chain funds"), "{}", reason);
+
+    service.node.set_onchain_balance(OnchainBalance {
+        confirmed_sat: 605_000,
+        unconfirmed_sat: 0,
+    });
+    let resp: OpenChannelResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+}
+
+// ============================================================================
+// LUD-03: Withdraw Request
+// ============================================================================
+
+async fn wallet_invoice(wallet: &MockBackend, amount_msat: u64) -> String {
+    let invoice = wallet
+        .create_invoice(InvoiceParams {
+            amount_msat,
+            description: "withdraw".to_string(),
+            label: format!("withdraw-{}", amount_msat),
+            expiry: Some(3600),
+            description_hash_only: false,
+        })
+        .await
+        .unwrap();
+    invoice.bolt11
+}
+
+#[tokio::test]
+async fn withdraw_request_pays_invoice() {
+    let service = spawn_service().await;
+    let wallet = MockBackend::new(2);
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    assert_eq!(req.tag, WITHDRAW_REQUEST_TAG);
+    assert!(req.min_withdrawable <= req.max_withdrawable);
+
+    let pr = wallet_invoice(&wallet, req.max_withdrawable).await;
+    let resp = service.get(callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)])).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp: WithdrawResponse = resp.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    // Paid in the background, once the callback has answered
+    let payout = service.payout(&req.k1).await;
+    assert_eq!(payout.status, "succeeded");
+    assert_eq!(payout.attempts, 1);
+
+    let payments = service.node.payments();
+    assert_eq!(payments.len(), 1);
+    assert_eq!(payments[0].amount_msat, req.max_withdrawable);
+    assert_eq!(payout.payment_hash.as_ref(), Some(&payments[0].payment_hash));
+}
+
+/// A mock invoice with arbitrary fields, for what `MockBackend` would never create
+fn crafted_invoice(payee: &MockBackend, amount_msat: Option<u64>, currency: &str, created_at: u64) -> String {
+    let invoice = serde_json::json!({
+        "payee": payee.node_id().to_string(),
+        "payment_hash": "00".repeat(32),
+        "amount_msat": amount_msat,
+        "description": "withdraw",
+        "description_hash": null,
+        "currency": currency,
+        "created_at": created_at,
+        "expiry": 3600,
+    });
+    format!("{}{}", MOCK_INVOICE_PREFIX, hex::encode(invoice.to_string()))
+}
+
+#[tokio::test]
+async fn withdraw_rejects_invalid_invoices_without_burning_k1() {
+    let service = spawn_service().await;
+    let wallet = MockBackend::new(2);
+    let now = chrono::Utc::now().timestamp() as u64;
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let rejected = [
+        (wallet_invoice(&wallet, req.max_withdrawable + 1).await, "between"),
+        (wallet_invoice(&wallet, req.min_withdrawable - 1).await, "between"),
+        (crafted_invoice(&wallet, None, "tb", now), "amountless"),
+        (crafted_invoice(&wallet, Some(req.min_withdrawable), "tb", now - 7200), "expired"),
+        (crafted_invoice(&wallet, Some(req.min_withdrawable), "bc", now), "network"),
+        (wallet_invoice(&service.node, req.min_withdrawable).await, "this service"),
+        ("lnbc1garbage".to_string(), "Invalid invoice"),
+    ];
+
+    for (pr, expected) in rejected {
+        let resp = service.get(callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)])).await;
+        let reason = error_reason(resp).await;
+        assert!(reason.contains(expected), "{} does not mention {}", reason, expected);
+    }
+    assert!(service.node.payments().is_empty());
+
+    // The k1 is still good for a valid invoice
+    let pr = wallet_invoice(&wallet, req.min_withdrawable).await;
+    let resp: WithdrawResponse = service
+        .get(callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(resp.status, "OK");
+}
+
+#[tokio::test]
+async fn withdraw_request_rejects_reused_k1() {
+    let service = spawn_service().await;
+    let wallet = MockBackend::new(2);
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let pr = wallet_invoice(&wallet, req.min_withdrawable).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+
+    assert_eq!(service.get(url.clone()).await.status(), StatusCode::OK);
+    service.payout(&req.k1).await;
+    let reason = error_reason(service.get(url).await).await;
+    assert_eq!(reason, ServiceError::UsedK1.to_string());
+    assert_eq!(service.node.payments().len(), 1);
+}
+
+#[tokio::test]
+async fn withdraw_request_reports_payment_failure() {
+    let service = spawn_service().await;
+    let wallet = MockBackend::new(2);
+    service.node.fail(MockCall::PayInvoice, "no route");
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let pr = wallet_invoice(&wallet, req.min_withdrawable).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    let resp: WithdrawResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    let payout = service.payout(&req.k1).await;
+    assert_eq!(payout.status, "failed");
+    let reason = payout.reason.unwrap();
+    assert!(reason.contains("no route"), "{}", reason);
+    assert!(service.node.payments().is_empty());
+}
+
+#[tokio::test]
+async fn payout_retries_transient_payment_failure() {
+    let service = spawn_service().await;
+    let wallet = MockBackend::new(2);
+    service.node.fail_with(MockCall::PayInvoice, Some(1), || BackendError::Rpc {
+        code: Some(205),
+        message: "Could not find a route".to_string(),
+    });
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let pr = wallet_invoice(&wallet, req.min_withdrawable).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+
+    let resp: WithdrawResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    let payout = service.payout(&req.k1).await;
+    assert_eq!((payout.status.as_str(), payout.attempts), ("succeeded", 2));
+    assert_eq!(service.node.payments().len(), 1);
+}
+
+#[tokio::test]
+async fn payout_gives_up_after_max_attempts() {
+    let service = spawn_service().await;
+    let wallet = MockBackend::new(2);
+    service.node.fail_with(MockCall::PayInvoice, None, || BackendError::Rpc {
+        code: Some(205),
+        message: "Could not find a route".to_string(),
+    });
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let pr = wallet_invoice(&wallet, req.min_withdrawable).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    assert_eq!(service.get(url.clone()).await.status(), StatusCode::OK);
+
+    let payout = service.payout(&req.k1).await;
+    assert_eq!(payout.status, "failed");
+    assert_eq!(payout.attempts, PayoutSettings::default().max_attempts);
+    assert_eq!(error_reason(service.get(url).await).await, ServiceError::UsedK1.to_string());
+}
+
+#[tokio::test]
+async fn payout_waits_for_pending_payment() {
+    let service = spawn_service().await;
+    let wallet = MockBackend::new(2);
+    service.node.hold_payments(true);
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let pr = wallet_invoice(&wallet, req.min_withdrawable).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    let resp: WithdrawResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    // In flight is not paid
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert_eq!(service.payout_now(&req.k1).await.status, "paying");
+
+    service.node.settle_payments(PaymentStatus::Complete);
+    let payout = service.payout(&req.k1).await;
+    assert_eq!(payout.status, "succeeded");
+    assert_eq!(service.node.pay_requests().len(), 1);
+}
+
+#[tokio::test]
+async fn payout_pending_too_long_goes_back_in_the_queue() {
+    let setup = Setup {
+        payout: PayoutSettings {
+            retry_secs: 0,
+            pending_poll_secs: 0,
+            pending_wait_secs: 0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let wallet = MockBackend::new(2);
+    service.node.hold_payments(true);
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let pr = wallet_invoice(&wallet, req.min_withdrawable).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    assert_eq!(service.get(url.clone()).await.status(), StatusCode::OK);
+
+    // Followed again and again, but never paid twice nor failed
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    let payout = service.payout_now(&req.k1).await;
+    assert!(payout.attempts > 1, "{:?}", payout);
+    assert!(payout.reason.unwrap().contains("still pending"));
+    assert_eq!(error_reason(service.get(url).await).await, ServiceError::K1InProgress.to_string());
+
+    service.node.settle_payments(PaymentStatus::Complete);
+    let payout = service.payout(&req.k1).await;
+    assert_eq!(payout.status, "succeeded");
+    assert_eq!(service.node.pay_requests().len(), 1);
+}
+
+#[tokio::test]
+async fn payout_lost_response_is_not_paid_twice() {
+    let service = spawn_service_with_links(Default::default(), faucet(2_000, 1)).await;
+    let wallet = MockBackend::new(2);
+    service.node.hold_payments(true);
+    service.node.lose_pay_responses(1);
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request/faucet").await;
+    let pr = wallet_invoice(&wallet, 2_000).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    let resp: WithdrawResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    // The connection dropped while the payment is still in flight: neither paid again nor refunded
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert_eq!(service.payout_now(&req.k1).await.status, "paying");
+    assert_eq!(service.node.pay_requests().len(), 1);
+    let url = Url::parse(&format!("{}/withdraw-request/faucet", service.base_url)).unwrap();
+    let reason = error_reason(service.get(url).await).await;
+    assert!(reason.contains("no uses left"), "{}", reason);
+
+    service.node.settle_payments(PaymentStatus::Complete);
+    let payout = service.payout(&req.k1).await;
+    assert_eq!(payout.status, "succeeded");
+    assert_eq!(service.node.pay_requests().len(), 1);
+    assert_eq!(service.node.payments().len(), 1);
+}
+
+#[tokio::test]
+async fn withdraw_status_of_unknown_k1() {
+    let service = spawn_service().await;
+
+    let url = Url::parse(&format!("{}/withdraw-status/{}", service.base_url, "00".repeat(32))).unwrap();
+    let reason = error_reason(service.get(url).await).await;
+    assert_eq!(reason, ServiceError::UnknownPayout.to_string());
+}
+
+#[tokio::test]
+async fn withdraw_k1_is_spent_by_definitive_payment_failure() {
+    let service = spawn_service().await;
+    let wallet = MockBackend::new(2);
+    service.node.fail_once(MockCall::PayInvoice, "invoice expired");
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let pr = wallet_invoice(&wallet, req.min_withdrawable).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+
+    assert_eq!(service.get(url.clone()).await.status(), StatusCode::OK);
+    let payout = service.payout(&req.k1).await;
+    assert_eq!((payout.status.as_str(), payout.attempts), ("failed", 1));
+    assert_eq!(error_reason(service.get(url).await).await, ServiceError::UsedK1.to_string());
+    assert!(service.node.payments().is_empty());
+}
+
+#[tokio::test]
+async fn concurrent_withdraw_callbacks_pay_once() {
+    let service = spawn_service().await;
+    let wallet = MockBackend::new(2);
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let first = wallet_invoice(&wallet, req.min_withdrawable).await;
+    let second = wallet_invoice(&wallet, req.max_withdrawable).await;
+
+    let (a, b) = tokio::join!(
+        service.get(callback(&req.callback, &[("k1", &req.k1), ("pr", &first)])),
+        service.get(callback(&req.callback, &[("k1", &req.k1), ("pr", &second)])),
+    );
+    let bodies = [a.text().await.unwrap(), b.text().await.unwrap()];
+    let succeeded = bodies
+        .iter()
+        .filter(|body| parse_lnurl_response::<WithdrawResponse>(body).is_ok())
+        .count();
+
+    assert_eq!(succeeded, 1, "{:?}", bodies);
+    service.payout(&req.k1).await;
+    assert_eq!(service.node.payments().len(), 1);
+}
+
+#[tokio::test]
+async fn withdraw_callback_rejects_expired_k1() {
+    let k1 = K1Settings {
+        withdraw_ttl_secs: 0,
+        ..Default::default()
+    };
+    let service = spawn_service_with_config(HashMap::new(), k1).await;
+    let wallet = MockBackend::new(2);
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let pr = wallet_invoice(&wallet, req.min_withdrawable).await;
+    let resp = service.get(callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)])).await;
+
+    assert_eq!(error_reason(resp).await, ServiceError::ExpiredK1.to_string());
+    assert!(service.node.payments().is_empty());
+}
+
+#[tokio::test]
+async fn withdraw_callback_rejects_unknown_k1() {
+    let service = spawn_service().await;
+    let pr = wallet_invoice(&MockBackend::new(2), 1_000).await;
+
+    let url = callback(
+        &format!("{}/withdraw-callback", service.base_url),
+        &[("k1", &"00".repeat(32)), ("pr", &pr)],
+    );
+    let reason = error_reason(service.get(url).await).await;
+    assert_eq!(reason, ServiceError::UnknownK1.to_string());
+}
+
+fn faucet(budget_msat: u64, uses: u32) -> HashMap<String, WithdrawLink> {
+    let link = WithdrawLink {
+        description: "Meetup faucet".to_string(),
+        budget_msat,
+        min_withdrawable: 1_000,
+        max_withdrawable: 2_000,
+        uses,
+        valid_from: None,
+        valid_until: None,
+        wait_secs: 0,
+        fees: Default::default(),
+    };
+    HashMap::from([("faucet".to_string(), link)])
+}
+
+#[tokio::test]
+async fn withdraw_link_spends_its_budget() {
+    let service = spawn_service_with_links(Default::default(), faucet(3_000, 5)).await;
+    let wallet = MockBackend::new(2);
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request/faucet").await;
+    assert_eq!(req.default_description, "Meetup faucet");
+    assert_eq!((req.min_withdrawable, req.max_withdrawable), (1_000, 2_000));
+    let pr = wallet_invoice(&wallet, 2_000).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    let resp: WithdrawResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    // Only what is left on the link is offered
+    let req: WithdrawRequestResponse = service.scan("withdraw-request/faucet").await;
+    assert_eq!(req.max_withdrawable, 1_000);
+    let pr = wallet_invoice(&wallet, 1_000).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    let resp: WithdrawResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    let url = Url::parse(&format!("{}/withdraw-request/faucet", service.base_url)).unwrap();
+    let reason = error_reason(service.get(url).await).await;
+    assert!(reason.contains("only 0 msat"), "{}", reason);
+
+    let url = Url::parse(&format!("{}/withdraw-request/nope", service.base_url)).unwrap();
+    let reason = error_reason(service.get(url).await).await;
+    assert_eq!(reason, ServiceError::UnknownWithdrawLink("nope".to_string()).to_string());
+    service.payout(&req.k1).await;
+    assert_eq!(service.node.payments().len(), 2);
+}
+
+#[tokio::test]
+async fn withdraw_link_is_checked_again_at_callback() {
+    let service = spawn_service_with_links(Default::default(), faucet(3_000, 5)).await;
+    let wallet = MockBackend::new(2);
+
+    // Both k1s are offered 2_000 msat, the budget only covers one of them
+    let first: WithdrawRequestResponse = service.scan("withdraw-request/faucet").await;
+    let second: WithdrawRequestResponse = service.scan("withdraw-request/faucet").await;
+
+    let pr = wallet_invoice(&wallet, 2_000).await;
+    let url = callback(&first.callback, &[("k1", &first.k1), ("pr", &pr)]);
+    let resp: WithdrawResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    let pr = wallet_invoice(&wallet, 2_000).await;
+    let url = callback(&second.callback, &[("k1", &second.k1), ("pr", &pr)]);
+    let reason = error_reason(service.get(url).await).await;
+    assert!(reason.contains("only 1000 msat"), "{}", reason);
+
+    // The k1 was released, a smaller invoice goes through
+    let pr = wallet_invoice(&wallet, 1_000).await;
+    let url = callback(&second.callback, &[("k1", &second.k1), ("pr", &pr)]);
+    let resp: WithdrawResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+    service.payout(&first.k1).await;
+    service.payout(&second.k1).await;
+    assert_eq!(service.node.payments().len(), 2);
+}
+
+#[tokio::test]
+async fn failed_link_payment_keeps_the_use() {
+    let service = spawn_service_with_links(Default::default(), faucet(3_000, 1)).await;
+    let wallet = MockBackend::new(2);
+    service.node.fail_once(MockCall::PayInvoice, "no route");
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request/faucet").await;
+    let pr = wallet_invoice(&wallet, 1_000).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    assert_eq!(service.get(url).await.status(), StatusCode::OK);
+    let reason = service.payout(&req.k1).await.reason.unwrap();
+    assert!(reason.contains("no route"), "{}", reason);
+
+    // Its only use was not spent by the failed payment
+    let req: WithdrawRequestResponse = service.scan("withdraw-request/faucet").await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    let resp: WithdrawResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+
+    let url = Url::parse(&format!("{}/withdraw-request/faucet", service.base_url)).unwrap();
+    let reason = error_reason(service.get(url).await).await;
+    assert!(reason.contains("no uses left"), "{}", reason);
+}
+
+#[tokio::test]
+async fn payout_records_routing_fee() {
+    let node = MockBackend::new(1).with_routing_fee(7);
+    let service = spawn_service_full(node, Setup::default()).await;
+    let wallet = MockBackend::new(2);
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let pr = wallet_invoice(&wallet, 1_000_000).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    assert_eq!(service.get(url).await.status(), StatusCode::OK);
+
+    let payout = service.payout(&req.k1).await;
+    assert_eq!((payout.status.as_str(), payout.fee_msat), ("succeeded", Some(7)));
+    // 1% of the amount by default
+    assert_eq!(service.node.pay_requests()[0].max_fee_msat, Some(10_000));
+}
+
+#[tokio::test]
+async fn payout_of_small_amount_gets_the_exempt_fee() {
+    let node = MockBackend::new(1).with_routing_fee(1_000);
+    let service = spawn_service_full(node, Setup::default()).await;
+    let wallet = MockBackend::new(2);
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request").await;
+    let amount_msat = 10_000;
+    let pr = wallet_invoice(&wallet, amount_msat).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    assert_eq!(service.get(url).await.status(), StatusCode::OK);
+
+    // 1% of 10 sat is below the base fee of any route, the exempt fee still allows it
+    let payout = service.payout(&req.k1).await;
+    assert_eq!((payout.status.as_str(), payout.fee_msat, payout.attempts), ("succeeded", Some(1_000), 1));
+    assert_eq!(service.node.pay_requests()[0].max_fee_msat, Some(5_000));
+
+    let fees = PayoutSettings::default().fees;
+    assert_eq!(fees.max_fee_for(amount_msat), Some(5_000));
+    let without_exemption = FeePolicy {
+        exempt_fee_msat: None,
+        ..fees.clone()
+    };
+    assert_eq!(without_exemption.max_fee_for(amount_msat), Some(amount_msat / 100));
+    let capped = FeePolicy {
+        max_fee_msat: Some(2_000),
+        ..fees
+    };
+    assert_eq!(capped.max_fee_for(amount_msat), Some(2_000));
+}
+
+#[tokio::test]
+async fn withdraw_link_routes_under_its_own_fee_policy() {
+    let mut links = faucet(3_000, 5);
+    links.get_mut("faucet").unwrap().fees = FeePolicy {
+        max_fee_msat: Some(5),
+        max_delay: Some(144),
+        exclude: vec!["103x1x0/1".to_string()],
+        ..Default::default()
+    };
+    let node = MockBackend::new(1).with_routing_fee(7);
+    let setup = Setup {
+        withdraw_links: links,
+        ..Default::default()
+    };
+    let service = spawn_service_full(node, setup).await;
+    let wallet = MockBackend::new(2);
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request/faucet").await;
+    let pr = wallet_invoice(&wallet, 2_000).await;
+    let url = callback(&req.callback, &[("k1", &req.k1), ("pr", &pr)]);
+    assert_eq!(service.get(url).await.status(), StatusCode::OK);
+
+    // Every route costs more than the link allows
+    let payout = service.payout(&req.k1).await;
+    assert_eq!(payout.status, "failed");
+    assert!(payout.reason.as_deref().unwrap_or_default().contains("fee"), "{:?}", payout.reason);
+    assert!(service.node.payments().is_empty());
+
+    let sent = &service.node.pay_requests()[0];
+    assert_eq!((sent.max_fee_msat, sent.max_delay), (Some(5), Some(144)));
+    assert_eq!(sent.exclude, ["103x1x0/1"]);
+
+    // Nothing was paid, the link keeps its budget
+    let req: WithdrawRequestResponse = service.scan("withdraw-request/faucet").await;
+    assert_eq!(req.max_withdrawable, 2_000);
+}
+
+#[tokio::test]
+async fn closed_withdraw_request_still_serves_links() {
+    // Closed unless configured otherwise
+    assert!(!WithdrawLimits::default().open);
+    let service = spawn_service_with_links(Default::default(), faucet(3_000, 1)).await;
+
+    for path in ["withdraw-request", "lnurl/withdraw-request"] {
+        let url = Url::parse(&format!("{}/{}", service.base_url, path)).unwrap();
+        let reason = error_reason(service.get(url).await).await;
+        assert!(reason.starts_with("Unknown LNURL endpoint"), "{}", reason);
+    }
+
+    let req: WithdrawRequestResponse = service.scan("withdraw-request/faucet").await;
+    assert_eq!(req.tag, WITHDRAW_REQUEST_TAG);
+}
+
+// ============================================================================
+// Internal API
+// ============================================================================
+
+const API_TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+#[tokio::test]
+async fn internal_open_channel_connects_and_funds() {
+    let setup = Setup {
+        internal_api_token: Some(API_TOKEN.to_string()),
+        channel: ChannelOffer {
+            push_msat: 10_000,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let peer = MockBackend::new(3).node_id();
+    let url = format!("{}/internal/open-channel", service.base_url);
+    let req = InternalOpenChannelRequest {
+        node_id: peer.to_string(),
+        host: "10.0.0.3".to_string(),
+        port: 9735,
+        satoshis: 250_000,
+        private: false,
+        push_msat: None,
+        skip_node_limits: false,
+    };
+
+    for token in [None, Some("wrong")] {
+        let mut call = service.http.post(&url).json(&req);
+        if let Some(token) = token {
+            call = call.bearer_auth(token);
+        }
+        let resp = call.send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let error: ErrorResponse = resp.json().await.unwrap();
+        assert_eq!(error.reason, ServiceError::Unauthorized.to_string());
+    }
+    assert!(service.node.peers().is_empty());
+
+    let resp = service.http.post(&url).bearer_auth(API_TOKEN).json(&req).send().await.unwrap();
+    let body = resp.text().await.unwrap();
+    let resp: InternalOpenChannelResponse = parse_lnurl_response(&body).unwrap();
+    assert_eq!(service.node.peers(), [peer]);
+    let channels = service.node.channels();
+    assert_eq!((channels[0].node_id, channels[0].amount_sat), (peer, 250_000));
+    assert_eq!((channels[0].announce, channels[0].push_msat), (true, 10_000));
+    assert_eq!((resp.outnum, resp.mindepth), (0, Some(3)));
+
+    // Recorded like the LNURL channels
+    let status_url = Url::parse(&format!("{}/channel-status/{}", service.base_url, resp.k1)).unwrap();
+    let status: ChannelStatusResponse = service.get(status_url).await.json().await.unwrap();
+    assert_eq!((status.remote_id, status.txid), (peer.to_string(), resp.txid));
+
+    service.node.fail(MockCall::ConnectPeer, "Connection refused");
+    let req = InternalOpenChannelRequest {
+        node_id: MockBackend::new(4).node_id().to_string(),
+        ..req
+    };
+    let resp = service.http.post(&url).bearer_auth(API_TOKEN).json(&req).send().await.unwrap();
+    let reason = error_reason(resp).await;
+    assert!(reason.contains("Connection refused"), "{}", reason);
+    assert_eq!(service.node.channels().len(), 1);
+}
+
+#[tokio::test]
+async fn internal_open_channel_follows_the_channel_policy() {
+    let peer = MockBackend::new(3).node_id();
+    let denied = MockBackend::new(4).node_id();
+    let setup = Setup {
+        internal_api_token: Some(API_TOKEN.to_string()),
+        channel_policy: ChannelPolicy {
+            max_channels_per_node: 1,
+            deny: vec![denied.to_string()],
+            onchain_reserve_sat: 100_000,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let url = format!("{}/internal/open-channel", service.base_url);
+    let open = |req: &InternalOpenChannelRequest| service.http.post(&url).bearer_auth(API_TOKEN).json(req).send();
+    let req = InternalOpenChannelRequest {
+        node_id: peer.to_string(),
+        host: "10.0.0.3".to_string(),
+        port: 9735,
+        satoshis: 250_000,
+        private: true,
+        push_msat: Some(0),
+        skip_node_limits: false,
+    };
+    open(&req).await.unwrap().json::<InternalOpenChannelResponse>().await.unwrap();
+    assert!(!service.node.channels()[0].announce);
+
+    // One channel per node, unless the limits are explicitly skipped
+    let reason = error_reason(open(&req).await.unwrap()).await;
+    assert!(reason.contains("the limit is 1"), "{}", reason);
+    let skip = InternalOpenChannelRequest {
+        skip_node_limits: true,
+        ..req
+    };
+    open(&skip).await.unwrap().json::<InternalOpenChannelResponse>().await.unwrap();
+
+    // Never the funds nor the deny list
+    service.node.set_onchain_balance(OnchainBalance { confirmed_sat: 300_000, unconfirmed_sat: 0 });
+    let reason = error_reason(open(&skip).await.unwrap()).await;
+    assert!(reason.contains("not enough confirmed on-chain funds"), "{}", reason);
+    let to_denied = InternalOpenChannelRequest {
+        node_id: denied.to_string(),
+        ..skip
+    };
+    let reason = error_reason(open(&to_denied).await.unwrap()).await;
+    assert!(reason.contains("denied"), "{}", reason);
+    assert_eq!(service.node.channels().len(), 2);
+}
+
+#[tokio::test]
+async fn internal_api_is_disabled_without_token() {
+    let service = spawn_service().await;
+    let req = InternalOpenChannelRequest {
+        node_id: MockBackend::new(3).node_id().to_string(),
+        host: "10.0.0.3".to_string(),
+        port: 9735,
+        satoshis: 250_000,
+        private: false,
+        push_msat: None,
+        skip_node_limits: false,
+    };
+
+    let url = format!("{}/internal/open-channel", service.base_url);
+    let resp = service.http.post(url).bearer_auth(API_TOKEN).json(&req).send().await.unwrap();
+    let reason = error_reason(resp).await;
+    assert_eq!(reason, ServiceError::UnknownEndpoint("internal/open-channel".to_string()).to_string());
+    assert!(service.node.channels().is_empty());
+}
+
+// ============================================================================
+// LUD-04: LNURL-auth
+// ============================================================================
+
+const SESSION_SECRET: &str = "fedcba9876543210fedcba9876543210";
+
+async fn auth_challenge(service: &TestService) -> AuthChallengeResponse {
+    let url = format!("{}/auth-challenge", service.base_url);
+    service.http.get(url).send().await.unwrap().json().await.unwrap()
+}
+
+async fn auth_url(service: &TestService) -> Url {
+    let challenge = auth_challenge(service).await;
+    let url = Url::parse(&decode_lnurl(&challenge.lnurl.unwrap()).unwrap()).unwrap();
+    assert!(url.query_pairs().any(|(k, v)| k == "k1" && v == challenge.k1));
+    assert!(url.query_pairs().any(|(k, v)| k == "tag" && v == AUTH_TAG));
+    url
+}
+
+fn signed(url: &Url, linking_key: &SecretKey) -> Url {
+    let k1 = url.query_pairs().find(|(k, _)| k == "k1").unwrap().1.into_owned();
+    let (sig, key) = sign_auth_challenge(&k1, linking_key).unwrap();
+
+    let mut url = url.clone();
+    url.query_pairs_mut().append_pair("sig", &sig).append_pair("key", &key);
+    url
+}
+
+#[tokio::test]
+async fn auth_accepts_der_signature() {
+    let service = spawn_service().await;
+    let linking_key = SecretKey::from_slice(&[7; 32]).unwrap();
+
+    let url = signed(&auth_url(&service).await, &linking_key);
+    let resp = service.get(url).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp: AuthResponse = resp.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+    assert_eq!(resp.event.as_deref(), Some("LOGGEDIN"));
+}
+
+#[tokio::test]
+async fn auth_rejects_signature_from_other_key() {
+    let service = spawn_service().await;
+    let url = auth_url(&service).await;
+    let k1 = url.query_pairs().find(|(k, _)| k == "k1").unwrap().1.into_owned();
+
+    // Signed by one key, presented with another
+    let mut forged = url.clone();
+    let (sig, _) = sign_auth_challenge(&k1, &SecretKey::from_slice(&[7; 32]).unwrap()).unwrap();
+    let (_, key) = sign_auth_challenge(&k1, &SecretKey::from_slice(&[8; 32]).unwrap()).unwrap();
+    forged.query_pairs_mut().append_pair("sig", &sig).append_pair("key", &key);
+
+    let reason = error_reason(service.get(forged).await).await;
+    assert_eq!(reason, ServiceError::InvalidAuth(AuthError::VerificationFailed).to_string());
+
+    // A bad signature does not spend the challenge of the wallet
+    let url = signed(&url, &SecretKey::from_slice(&[8; 32]).unwrap());
+    let resp: AuthResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.event.as_deref(), Some("LOGGEDIN"));
+}
+
+#[tokio::test]
+async fn auth_rejects_reused_k1() {
+    let service = spawn_service().await;
+    let url = signed(&auth_url(&service).await, &SecretKey::from_slice(&[7; 32]).unwrap());
+
+    assert_eq!(service.get(url.clone()).await.status(), StatusCode::OK);
+    let reason = error_reason(service.get(url).await).await;
+    assert_eq!(reason, ServiceError::UsedK1.to_string());
+}
+
+#[tokio::test]
+async fn auth_k1_cannot_be_replayed_as_withdraw() {
+    let service = spawn_service().await;
+    let auth = auth_url(&service).await;
+    let k1 = auth.query_pairs().find(|(k, _)| k == "k1").unwrap().1.into_owned();
+    let pr = wallet_invoice(&MockBackend::new(2), 1_000).await;
+
+    let url = callback(&format!("{}/withdraw-callback", service.base_url), &[("k1", &k1), ("pr", &pr)]);
+    let reason = error_reason(service.get(url).await).await;
+    assert_eq!(
+        reason,
+        ServiceError::WrongK1 {
+            issued: K1Kind::Auth,
+            expected: K1Kind::Withdraw
+        }
+        .to_string()
+    );
+    assert!(service.node.payments().is_empty());
+
+    // The challenge is still good for logging in
+    let url = signed(&auth, &SecretKey::from_slice(&[7; 32]).unwrap());
+    let resp: AuthResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.status, "OK");
+}
+
+impl TestService {
+    /// Poll the login of `k1` as the page showing its QR would
+    async fn auth_session(&self, k1: &str, session: &str) -> reqwest::Response {
+        self.get(callback(&format!("{}/auth-session", self.base_url), &[("k1", k1), ("session", session)])).await
+    }
+
+    async fn with_token(&self, request: reqwest::RequestBuilder, token: &str) -> StatusCode {
+        request.bearer_auth(token).send().await.unwrap().status()
+    }
+}
+
+#[tokio::test]
+async fn auth_login_opens_a_session_for_the_page() {
+    let service = spawn_service().await;
+    let linking_key = SecretKey::from_slice(&[7; 32]).unwrap();
+    let challenge = auth_challenge(&service).await;
+    let session = challenge.session.unwrap();
+
+    let pending: AuthSessionResponse = service.auth_session(&challenge.k1, &session).await.json().await.unwrap();
+    assert_eq!((pending.state.as_str(), pending.token), ("pending", None));
+    // Only the page that got the challenge can wait on it
+    let resp = service.auth_session(&challenge.k1, &"0".repeat(64)).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let url = Url::parse(&decode_lnurl(&challenge.lnurl.unwrap()).unwrap()).unwrap();
+    let resp: AuthResponse = service.get(signed(&url, &linking_key)).await.json().await.unwrap();
+    assert_eq!(resp.event.as_deref(), Some("LOGGEDIN"));
+
+    let logged_in: AuthSessionResponse = service.auth_session(&challenge.k1, &session).await.json().await.unwrap();
+    assert_eq!(logged_in.state, "loggedin");
+    let (_, key) = sign_auth_challenge(&challenge.k1, &linking_key).unwrap();
+    assert_eq!(logged_in.key, Some(key.clone()));
+    let token = logged_in.token.unwrap();
+
+    let session_url = format!("{}/session", service.base_url);
+    let resp = service.http.get(&session_url).bearer_auth(&token).send().await.unwrap();
+    let current: SessionResponse = resp.json().await.unwrap();
+    assert_eq!((current.key, Some(current.expires_at)), (key, logged_in.expires_at));
+
+    // A token is only good with its signature
+    let last = if token.ends_with('0') { '1' } else { '0' };
+    let forged = format!("{}{}", &token[..token.len() - 1], last);
+    let status = service.with_token(service.http.get(&session_url), &forged).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let logout_url = format!("{}/logout", service.base_url);
+    assert_eq!(service.with_token(service.http.post(&logout_url), &token).await, StatusCode::OK);
+    assert_eq!(service.with_token(service.http.get(&session_url), &token).await, StatusCode::UNAUTHORIZED);
+    let resp = service.auth_session(&challenge.k1, &session).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn auth_session_swept_after_login_is_not_pending() {
+    let setup = Setup {
+        session_ttl_secs: 1,
+        ..Default::default()
+    };
+    let service = spawn_service_full(MockBackend::new(1), setup).await;
+    let challenge = auth_challenge(&service).await;
+    let session = challenge.session.unwrap();
+    let url = Url::parse(&decode_lnurl(&challenge.lnurl.unwrap()).unwrap()).unwrap();
+    let url = signed(&url, &SecretKey::from_slice(&[7; 32]).unwrap());
+    let resp: AuthResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.event.as_deref(), Some("LOGGEDIN"));
+
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    service.storage.sweep().await.unwrap();
+    assert!(service.storage.get_k1(&challenge.k1).await.unwrap().is_some());
+
+    // The k1 says logged in, the session is gone: the page must start over
+    let resp = service.auth_session(&challenge.k1, &session).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let error: ErrorResponse = resp.json().await.unwrap();
+    assert_eq!(error.reason, ServiceError::InvalidSession.to_string());
+}
+
+#[tokio::test]
+async fn auth_bad_signature_keeps_the_page_waiting() {
+    let service = spawn_service().await;
+    let challenge = auth_challenge(&service).await;
+    let session = challenge.session.unwrap();
+    let url = Url::parse(&decode_lnurl(&challenge.lnurl.unwrap()).unwrap()).unwrap();
+
+    let mut forged = url.clone();
+    let (sig, _) = sign_auth_challenge(&challenge.k1, &SecretKey::from_slice(&[7; 32]).unwrap()).unwrap();
+    let (_, key) = sign_auth_challenge(&challenge.k1, &SecretKey::from_slice(&[8; 32]).unwrap()).unwrap();
+    forged.query_pairs_mut().append_pair("sig", &sig).append_pair("key", &key);
+    let reason = error_reason(service.get(forged).await).await;
+    assert_eq!(reason, ServiceError::InvalidAuth(AuthError::VerificationFailed).to_string());
+
+    // Nobody logged in, the challenge is still there for the wallet
+    let pending: AuthSessionResponse = service.auth_session(&challenge.k1, &session).await.json().await.unwrap();
+    assert_eq!((pending.state.as_str(), pending.token), ("pending", None));
+
+    let url = signed(&url, &SecretKey::from_slice(&[8; 32]).unwrap());
+    let resp: AuthResponse = service.get(url).await.json().await.unwrap();
+    assert_eq!(resp.event.as_deref(), Some("LOGGEDIN"));
+    let logged_in: AuthSessionResponse = service.auth_session(&challenge.k1, &session).await.json().await.unwrap();
+    assert_eq!((logged_in.state.as_str(), logged_in.key), ("loggedin", Some(key)));
+}
+
+// ============================================================================
+// LUD-06 / LUD-16: Pay Request and Lightning Address
+// ============================================================================
+
+#[tokio::test]
+async fn pay_request_invoice_commits_to_metadata() {
+    let service = spawn_service().await;
+
+    let req: PayRequestResponse = service.scan("pay-request").await;
+    assert_eq!(req.tag, PAY_REQUEST_TAG);
+
+    let amount = req.min_sendable.to_string();
+    let resp: PayCallbackResponse = service
+        .get(callback(&req.callback, &[("amount", &amount)]))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    let decoded = service.node.decode_invoice(&resp.pr).await.unwrap();
+    assert_eq!(decoded.description_hash, Some(metadata_hash(&req.metadata)));
+    assert_eq!(decoded.amount_msat, Some(req.min_sendable));
+}
+
+#[tokio::test]
+async fn pay_callback_rejects_amount_out_of_bounds() {
+    let service = spawn_service().await;
+
+    let req: PayRequestResponse = service.scan("pay-request").await;
+    let amount = (req.max_sendable + 1).to_string();
+    let resp = service.get(callback(&req.callback, &[("amount", &amount)])).await;
+
+    let reason = error_reason(resp).await;
+    assert_eq!(
+        reason,
+        ServiceError::AmountOutOfBounds {
+            min: req.min_sendable,
+            max: req.max_sendable
+        }
+        .to_string()
+    );
+    assert!(service.node.invoices().is_empty());
+}
+
+#[tokio::test]
+async fn lightning_address_resolves_registered_user() {
+    let users = HashMap::from([(
+        "alice".to_string(),
+        LightningAddressUser {
+            description: "Tips for Alice".to_string(),
+            min_sendable: None,
+            max_sendable: Some(50_000),
+        },
+    )]);
+    let service = spawn_service_with(users).await;
+
+    let url = format!("{}/.well-known/lnurlp/alice", service.base_url);
+    let req: PayRequestResponse = service.http.get(url).send().await.unwrap().json().await.unwrap();
+    assert_eq!(req.max_sendable, 50_000);
+    assert!(req.metadata.contains(r#"["text/identifier","alice@example.com"]"#));
+
+    let resp: PayCallbackResponse = service
+        .get(callback(&req.callback, &[("amount", "2000")]))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let decoded = service.node.decode_invoice(&resp.pr).await.unwrap();
+    assert_eq!(decoded.description_hash, Some(metadata_hash(&req.metadata)));
+
+    let unknown = format!("{}/.well-known/lnurlp/bob", service.base_url);
+    let reason = error_reason(service.http.get(unknown).send().await.unwrap()).await;
+    assert_eq!(reason, ServiceError::UnknownUser("bob".to_string()).to_string());
+}
+
+#[test]
+fn lnurl_round_trip() {
+    let url = "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df";
+    let lnurl = encode_lnurl(url).unwrap();
+
+    assert_eq!(
+        lnurl.to_uppercase(),
+        "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS"
+    );
+    assert_eq!(decode_lnurl(&lnurl.to_uppercase()).unwrap(), url);
+    assert_eq!(decode_lnurl(&format!("lightning:{}", lnurl)).unwrap(), url);
+}
+
+#[test]
+fn error_response_surfaces_reason() {
+    let body = serde_json::to_string(&ErrorResponse::from(&ServiceError::UsedK1)).unwrap();
+    assert_eq!(body, r#"{"status":"ERROR","reason":"This link has already been used"}"#);
+    assert_eq!(
+        parse_lnurl_response::<WithdrawResponse>(&body).unwrap_err(),
+        "This link has already been used"
+    );
+    assert_eq!(parse_lnurl_response::<WithdrawResponse>(r#"{"status":"OK"}"#).unwrap().status, "OK");
+}
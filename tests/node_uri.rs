@@ -0,0 +1,85 @@
+//! Node URI derivation from getinfo
+
+use lnurl_project::backend::{AddressKind, MockBackend, NodeAddress};
+use lnurl_project::config::parse_node_uri;
+use lnurl_project::node_uri::{resolve_node_uri, DEFAULT_ADVERTISE};
+
+fn address(kind: AddressKind, address: &str) -> NodeAddress {
+    NodeAddress {
+        kind,
+        address: address.to_string(),
+        port: 9735,
+    }
+}
+
+#[tokio::test]
+async fn derives_uri_from_announced_address() {
+    let node = MockBackend::new(1).with_address(address(AddressKind::Ipv4, "203.0.113.7"));
+
+    let uri = resolve_node_uri(&node, None, DEFAULT_ADVERTISE).await.unwrap();
+    assert_eq!(uri, format!("{}@203.0.113.7:9735", node.node_id()));
+}
+
+#[tokio::test]
+async fn follows_advertise_preference() {
+    let onion = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion";
+    let node = MockBackend::new(1)
+        .with_address(address(AddressKind::Ipv4, "203.0.113.7"))
+        .with_address(address(AddressKind::Ipv6, "2001:db8::7"))
+        .with_address(address(AddressKind::TorV3, onion));
+
+    let uri = resolve_node_uri(&node, None, &[AddressKind::TorV3]).await.unwrap();
+    assert_eq!(uri, format!("{}@{}:9735", node.node_id(), onion));
+
+    // IPv6 hosts are bracketed so the port stays unambiguous
+    let uri = resolve_node_uri(&node, None, &[AddressKind::Ipv6, AddressKind::Ipv4]).await.unwrap();
+    assert_eq!(uri, format!("{}@[2001:db8::7]:9735", node.node_id()));
+}
+
+#[tokio::test]
+async fn fails_without_matching_address() {
+    let node = MockBackend::new(1).with_address(address(AddressKind::TorV3, "example.onion"));
+
+    assert!(resolve_node_uri(&node, None, &[AddressKind::Ipv4]).await.is_err());
+}
+
+#[tokio::test]
+async fn accepts_configured_uri_of_this_node() {
+    let node = MockBackend::new(1).with_address(address(AddressKind::Ipv6, "2001:db8::7"));
+    let configured = format!("{}@[2001:db8::7]:9735", node.node_id());
+
+    let uri = resolve_node_uri(&node, Some(&configured), DEFAULT_ADVERTISE).await.unwrap();
+    assert_eq!(uri, configured);
+}
+
+#[tokio::test]
+async fn rejects_configured_uri_of_another_node() {
+    let node = MockBackend::new(1).with_address(address(AddressKind::Ipv4, "203.0.113.7"));
+    let configured = format!("{}@203.0.113.7:9735", MockBackend::new(2).node_id());
+
+    let err = resolve_node_uri(&node, Some(&configured), DEFAULT_ADVERTISE).await.unwrap_err();
+    assert!(err.contains(&node.node_id().to_string()), "{}", err);
+}
+
+#[tokio::test]
+async fn rejects_configured_uri_not_announced() {
+    let node = MockBackend::new(1).with_address(address(AddressKind::Ipv4, "203.0.113.7"));
+    let configured = format!("{}@198.51.100.1:9735", node.node_id());
+
+    assert!(resolve_node_uri(&node, Some(&configured), DEFAULT_ADVERTISE).await.is_err());
+}
+
+#[tokio::test]
+async fn client_splits_the_uri_it_is_given() {
+    let node = MockBackend::new(1).with_address(address(AddressKind::Ipv6, "2001:db8::7"));
+    let uri = resolve_node_uri(&node, None, DEFAULT_ADVERTISE).await.unwrap();
+
+    // What the client connects to from a channel request, the brackets stay for `connect`
+    let (id, host, port) = parse_node_uri(&uri).unwrap();
+    assert_eq!((id, host.as_str(), port), (node.node_id(), "[2001:db8::7]", 9735));
+
+    let (_, host, port) = parse_node_uri(&format!("{}@203.0.113.7:9736", node.node_id())).unwrap();
+    assert_eq!((host.as_str(), port), ("203.0.113.7", 9736));
+    assert!(parse_node_uri(&format!("{}@[2001:db8::7]", node.node_id())).is_err());
+    assert!(parse_node_uri("203.0.113.7:9735").is_err());
+}
@@ -0,0 +1,481 @@
+//! k1 storage: used-once, expiry, eviction and persistence, for every backend
+
+use lnurl_project::backend::ChannelState;
+use lnurl_project::channels::{ChannelLease, OpenedChannel};
+use lnurl_project::sessions::AuthSession;
+use lnurl_project::config::ChannelOffer;
+use lnurl_project::k1::{K1Error, K1Kind, K1Outcome, K1Purpose, K1Status};
+use lnurl_project::payout::{PayoutResult, PayoutStatus};
+use lnurl_project::storage::{MemoryStorage, SqliteStorage, Storage, StorageError};
+use lnurl_project::withdraw_links::{LinkError, LinkUsage, WithdrawLink};
+use std::time::Duration;
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+fn auth() -> K1Purpose {
+    K1Purpose::Auth {
+        action: "login".to_string(),
+        session_hash: None,
+    }
+}
+
+fn storages(max_entries: usize) -> Vec<Box<dyn Storage>> {
+    vec![
+        Box::new(MemoryStorage::new(max_entries)),
+        Box::new(SqliteStorage::open_in_memory(max_entries).unwrap()),
+    ]
+}
+
+fn link(uses: u32, wait_secs: u64) -> WithdrawLink {
+    WithdrawLink {
+        description: "faucet".to_string(),
+        budget_msat: 10_000,
+        min_withdrawable: 1_000,
+        max_withdrawable: 4_000,
+        uses,
+        valid_from: None,
+        valid_until: None,
+        wait_secs,
+        fees: Default::default(),
+    }
+}
+
+async fn reserve(storage: &dyn Storage, link: &WithdrawLink, k1: &str, amount_msat: u64) -> Result<(), LinkError> {
+    storage.reserve_withdrawal("faucet", link, k1, amount_msat).await.map_err(|e| match e {
+        StorageError::Link(e) => e,
+        e => panic!("unexpected storage error: {}", e),
+    })
+}
+
+async fn consume(storage: &dyn Storage, k1: &str, kind: K1Kind) -> Result<K1Purpose, K1Error> {
+    storage.consume_k1(k1, kind).await.map_err(|e| match e {
+        StorageError::K1(e) => e,
+        e => panic!("unexpected storage error: {}", e),
+    })
+}
+
+#[tokio::test]
+async fn k1_is_used_once() {
+    for storage in storages(10) {
+        storage.insert_k1("a", &auth(), HOUR).await.unwrap();
+
+        assert_eq!(consume(storage.as_ref(), "a", K1Kind::Auth).await, Ok(auth()));
+        let outcome = K1Outcome::Failed("bad signature".to_string());
+        storage.record_outcome("a", &outcome).await.unwrap();
+
+        assert_eq!(consume(storage.as_ref(), "a", K1Kind::Auth).await, Err(K1Error::Used));
+        assert_eq!(consume(storage.as_ref(), "b", K1Kind::Auth).await, Err(K1Error::Unknown));
+    }
+}
+
+#[tokio::test]
+async fn expired_k1_is_rejected_then_swept() {
+    for storage in storages(10) {
+        storage.insert_k1("expired", &auth(), Duration::ZERO).await.unwrap();
+        storage.insert_k1("live", &auth(), HOUR).await.unwrap();
+
+        assert_eq!(consume(storage.as_ref(), "expired", K1Kind::Auth).await, Err(K1Error::Expired));
+        assert_eq!(storage.sweep().await.unwrap(), 1);
+        assert_eq!(consume(storage.as_ref(), "expired", K1Kind::Auth).await, Err(K1Error::Unknown));
+        assert!(consume(storage.as_ref(), "live", K1Kind::Auth).await.is_ok());
+    }
+}
+
+#[tokio::test]
+async fn full_storage_evicts_expired_then_oldest() {
+    for storage in storages(3) {
+        storage.insert_k1("expired", &auth(), Duration::ZERO).await.unwrap();
+        storage.insert_k1("old", &auth(), HOUR).await.unwrap();
+        storage.insert_k1("mid", &auth(), HOUR).await.unwrap();
+
+        // The expired entry makes room first
+        storage.insert_k1("new", &auth(), HOUR).await.unwrap();
+        assert!(storage.get_k1("expired").await.unwrap().is_none());
+        assert!(storage.get_k1("old").await.unwrap().is_some());
+
+        // Then the oldest live one goes
+        storage.insert_k1("newest", &auth(), HOUR).await.unwrap();
+        assert!(storage.get_k1("old").await.unwrap().is_none());
+        for k1 in ["mid", "new", "newest"] {
+            assert!(storage.get_k1(k1).await.unwrap().is_some(), "{} was evicted", k1);
+        }
+    }
+}
+
+#[tokio::test]
+async fn in_progress_k1_survives_a_full_cache_and_a_sweep() {
+    for storage in storages(2) {
+        storage.insert_k1("held", &auth(), Duration::from_secs(1)).await.unwrap();
+        consume(storage.as_ref(), "held", K1Kind::Auth).await.unwrap();
+        storage.insert_k1("old", &auth(), HOUR).await.unwrap();
+
+        // The oldest k1 a callback does not hold is evicted
+        storage.insert_k1("new", &auth(), HOUR).await.unwrap();
+        assert!(storage.get_k1("old").await.unwrap().is_none());
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(storage.sweep().await.unwrap(), 0);
+        let record = storage.get_k1("held").await.unwrap().unwrap();
+        assert_eq!(record.status, K1Status::InProgress);
+        storage.record_outcome("held", &K1Outcome::Succeeded("key".to_string())).await.unwrap();
+        assert_eq!(storage.sweep().await.unwrap(), 1);
+    }
+}
+
+#[tokio::test]
+async fn k1_only_serves_the_flow_it_was_issued_for() {
+    let withdraw = K1Purpose::Withdraw {
+        min_withdrawable: 1_000,
+        max_withdrawable: 5_000,
+        link: None,
+    };
+
+    for storage in storages(10) {
+        storage.insert_k1("a", &auth(), HOUR).await.unwrap();
+        storage.insert_k1("w", &withdraw, HOUR).await.unwrap();
+
+        assert_eq!(
+            consume(storage.as_ref(), "a", K1Kind::Withdraw).await,
+            Err(K1Error::WrongKind(K1Kind::Auth))
+        );
+        assert_eq!(
+            consume(storage.as_ref(), "w", K1Kind::Channel).await,
+            Err(K1Error::WrongKind(K1Kind::Withdraw))
+        );
+
+        // A rejected attempt does not burn the k1
+        assert_eq!(consume(storage.as_ref(), "w", K1Kind::Withdraw).await, Ok(withdraw.clone()));
+        assert_eq!(consume(storage.as_ref(), "a", K1Kind::Auth).await, Ok(auth()));
+    }
+}
+
+#[tokio::test]
+async fn outcome_is_recorded() {
+    for storage in storages(10) {
+        storage.insert_k1("a", &auth(), HOUR).await.unwrap();
+        assert_eq!(storage.get_k1("a").await.unwrap().unwrap().status, K1Status::Issued);
+
+        consume(storage.as_ref(), "a", K1Kind::Auth).await.unwrap();
+        assert_eq!(storage.get_k1("a").await.unwrap().unwrap().status, K1Status::InProgress);
+
+        let outcome = K1Outcome::Failed("no route".to_string());
+        storage.record_outcome("a", &outcome).await.unwrap();
+        let record = storage.get_k1("a").await.unwrap().unwrap();
+        assert_eq!(record.status, K1Status::Failed);
+        assert_eq!(record.outcome.as_deref(), Some("no route"));
+    }
+}
+
+#[tokio::test]
+async fn cancelled_k1_cannot_be_used() {
+    for storage in storages(10) {
+        let channel = K1Purpose::Channel {
+            offer: ChannelOffer::default(),
+        };
+        storage.insert_k1("c", &channel, HOUR).await.unwrap();
+        consume(storage.as_ref(), "c", K1Kind::Channel).await.unwrap();
+
+        storage.record_outcome("c", &K1Outcome::Cancelled("wallet".to_string())).await.unwrap();
+        let record = storage.get_k1("c").await.unwrap().unwrap();
+        assert_eq!((record.status, record.outcome.as_deref()), (K1Status::Cancelled, Some("wallet")));
+        assert_eq!(consume(storage.as_ref(), "c", K1Kind::Channel).await, Err(K1Error::Cancelled));
+    }
+}
+
+#[tokio::test]
+async fn in_progress_k1_is_exclusive_until_released() {
+    for storage in storages(10) {
+        storage.insert_k1("a", &auth(), HOUR).await.unwrap();
+
+        consume(storage.as_ref(), "a", K1Kind::Auth).await.unwrap();
+        assert_eq!(consume(storage.as_ref(), "a", K1Kind::Auth).await, Err(K1Error::InProgress));
+
+        // A transient failure hands it back
+        storage.release_k1("a").await.unwrap();
+        assert_eq!(storage.get_k1("a").await.unwrap().unwrap().status, K1Status::Issued);
+        consume(storage.as_ref(), "a", K1Kind::Auth).await.unwrap();
+
+        // Once settled, releasing is a no-op
+        storage
+            .record_outcome("a", &K1Outcome::Succeeded("key".to_string()))
+            .await
+            .unwrap();
+        storage.release_k1("a").await.unwrap();
+        assert_eq!(consume(storage.as_ref(), "a", K1Kind::Auth).await, Err(K1Error::Used));
+    }
+}
+
+fn channel(k1: &str, remote_id: &str) -> OpenedChannel {
+    OpenedChannel {
+        k1: k1.to_string(),
+        remote_id: remote_id.to_string(),
+        channel_id: format!("channel-{}", k1),
+        txid: format!("tx-{}", k1),
+        outnum: 0,
+        capacity_sat: 100_000,
+        state: ChannelState::AwaitingLockin,
+        short_channel_id: None,
+        opened_at: 1,
+        updated_at: 1,
+        lease: None,
+    }
+}
+
+#[tokio::test]
+async fn channels_are_followed_until_closed() {
+    for storage in storages(10) {
+        storage.record_channel(&channel("a", "alice")).await.unwrap();
+        let lease = ChannelLease {
+            request_sat: 500_000,
+            compact_lease: "029a00640064000000644c4b40".to_string(),
+            fee_msat: 6_000_000,
+        };
+        let leased = OpenedChannel {
+            lease: Some(lease),
+            ..channel("b", "bob")
+        };
+        storage.record_channel(&leased).await.unwrap();
+        storage.record_channel(&channel("c", "alice")).await.unwrap();
+        assert_eq!(storage.get_channel("b").await.unwrap().unwrap().lease, leased.lease);
+
+        storage.update_channel("channel-a", ChannelState::Normal, Some("1x2x0")).await.unwrap();
+        // A later update without a short channel id keeps it
+        storage.update_channel("channel-a", ChannelState::Closing, None).await.unwrap();
+        storage.update_channel("channel-b", ChannelState::Closed, None).await.unwrap();
+
+        let a = storage.get_channel("a").await.unwrap().unwrap();
+        assert_eq!((a.state, a.short_channel_id.as_deref()), (ChannelState::Closing, Some("1x2x0")));
+        assert!(a.updated_at > a.opened_at);
+
+        let open: Vec<_> = storage.open_channels().await.unwrap().into_iter().map(|c| c.k1).collect();
+        assert_eq!(open, ["a", "c"]);
+        let alice: Vec<_> = storage.channels_of("alice").await.unwrap().into_iter().map(|c| c.k1).collect();
+        assert_eq!(alice, ["a", "c"]);
+        assert!(storage.get_channel("d").await.unwrap().is_none());
+
+        let recent = OpenedChannel {
+            opened_at: 1_000,
+            ..channel("d", "carol")
+        };
+        storage.record_channel(&recent).await.unwrap();
+        let since: Vec<_> = storage.channels_opened_since(1_000).await.unwrap().into_iter().map(|c| c.k1).collect();
+        assert_eq!(since, ["d"]);
+        assert_eq!(storage.channels_opened_since(1).await.unwrap().len(), 4);
+    }
+}
+
+#[tokio::test]
+async fn link_withdrawals_spend_budget_and_uses() {
+    for storage in storages(10) {
+        let link = link(3, 0);
+        reserve(storage.as_ref(), &link, "a", 4_000).await.unwrap();
+        reserve(storage.as_ref(), &link, "b", 4_000).await.unwrap();
+
+        // 2_000 msat left, a third use cannot take more
+        assert_eq!(
+            reserve(storage.as_ref(), &link, "c", 3_000).await,
+            Err(LinkError::BudgetExhausted { remaining_msat: 2_000 })
+        );
+        reserve(storage.as_ref(), &link, "c", 2_000).await.unwrap();
+
+        let usage = storage.link_usage("faucet").await.unwrap();
+        assert_eq!((usage.uses, usage.spent_msat), (3, 10_000));
+        assert_eq!(storage.link_usage("other").await.unwrap(), LinkUsage::default());
+    }
+}
+
+#[tokio::test]
+async fn cancelled_link_withdrawal_gives_back_its_use() {
+    for storage in storages(10) {
+        let link = link(1, 0);
+        reserve(storage.as_ref(), &link, "a", 1_000).await.unwrap();
+        assert_eq!(reserve(storage.as_ref(), &link, "b", 1_000).await, Err(LinkError::UsesExhausted));
+
+        storage.cancel_withdrawal("a").await.unwrap();
+        reserve(storage.as_ref(), &link, "b", 1_000).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn link_withdrawals_wait_between_uses() {
+    for storage in storages(10) {
+        let link = link(5, 3600);
+        reserve(storage.as_ref(), &link, "a", 1_000).await.unwrap();
+
+        match reserve(storage.as_ref(), &link, "b", 1_000).await {
+            Err(LinkError::TooSoon { retry_in_secs }) => assert!(retry_in_secs > 3500),
+            other => panic!("expected TooSoon, got {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn payouts_are_claimed_once_and_retried_when_due() {
+    for storage in storages(10) {
+        storage.queue_payout("a", "lnmock-a", 1_000, Some("faucet")).await.unwrap();
+        storage.queue_payout("b", "lnmock-b", 2_000, None).await.unwrap();
+
+        // Oldest first, within the limit
+        let claimed = storage.claim_payouts(1).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!((claimed[0].k1.as_str(), claimed[0].link.as_deref()), ("a", Some("faucet")));
+        assert_eq!((claimed[0].status, claimed[0].attempts), (PayoutStatus::Paying, 1));
+
+        let claimed = storage.claim_payouts(10).await.unwrap();
+        assert_eq!(claimed.iter().map(|p| p.k1.as_str()).collect::<Vec<_>>(), ["b"]);
+
+        // A retry waits until it is due
+        let retry = PayoutResult::Retry {
+            error: "no route".to_string(),
+            at: u64::MAX / 2,
+        };
+        storage.finish_payout("a", &retry).await.unwrap();
+        assert!(storage.claim_payouts(10).await.unwrap().is_empty());
+
+        let paid = PayoutResult::Paid {
+            payment_hash: "hash".to_string(),
+            fee_msat: 3,
+        };
+        storage.finish_payout("b", &paid).await.unwrap();
+        let payout = storage.get_payout("b").await.unwrap().unwrap();
+        assert_eq!((payout.status, payout.payment_hash.as_deref()), (PayoutStatus::Succeeded, Some("hash")));
+        assert_eq!(payout.fee_msat, Some(3));
+
+        let payout = storage.get_payout("a").await.unwrap().unwrap();
+        assert_eq!((payout.status, payout.error.as_deref()), (PayoutStatus::Queued, Some("no route")));
+        assert!(storage.get_payout("c").await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn interrupted_payouts_are_requeued() {
+    for storage in storages(10) {
+        storage.queue_payout("a", "lnmock-a", 1_000, None).await.unwrap();
+        storage.queue_payout("b", "lnmock-b", 1_000, None).await.unwrap();
+        storage.claim_payouts(10).await.unwrap();
+        let failed = PayoutResult::Failed {
+            error: "expired".to_string(),
+        };
+        storage.finish_payout("b", &failed).await.unwrap();
+
+        assert_eq!(storage.requeue_payouts().await.unwrap(), 1);
+        let claimed = storage.claim_payouts(10).await.unwrap();
+        assert_eq!((claimed.len(), claimed[0].attempts), (1, 2));
+    }
+}
+
+#[tokio::test]
+async fn sqlite_upgrades_older_schemas() {
+    let path = std::env::temp_dir().join(format!("lnurl-test-migrate-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // A database left by the first schema version
+    {
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE k1 (
+                k1 TEXT PRIMARY KEY NOT NULL,
+                kind TEXT NOT NULL,
+                purpose TEXT NOT NULL,
+                status TEXT NOT NULL,
+                outcome TEXT,
+                created_at INTEGER NOT NULL,
+                expires_at INTEGER NOT NULL
+            );
+            CREATE INDEX k1_expires_at ON k1 (expires_at);
+            INSERT INTO k1 VALUES
+                ('a', 'login', '{\"kind\":\"auth\",\"action\":\"login\"}', 'issued', NULL, 0, 4102444800);
+            PRAGMA user_version = 1;",
+        )
+        .unwrap();
+    }
+
+    // Its k1s are kept and the tables added since are there
+    let storage = SqliteStorage::open(&path, 10).unwrap();
+    assert_eq!(consume(&storage, "a", K1Kind::Auth).await, Ok(auth()));
+    storage.queue_payout("a", "lnmock-a", 1_000, None).await.unwrap();
+    storage.record_channel(&channel("opened", "alice")).await.unwrap();
+    assert_eq!(storage.get_channel("opened").await.unwrap(), Some(channel("opened", "alice")));
+
+    drop(storage);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn sqlite_keeps_spent_k1_across_restart() {
+    let path = std::env::temp_dir().join(format!("lnurl-test-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    {
+        let storage = SqliteStorage::open(&path, 10).unwrap();
+        storage.insert_k1("spent", &auth(), HOUR).await.unwrap();
+        storage.insert_k1("pending", &auth(), HOUR).await.unwrap();
+        consume(&storage, "spent", K1Kind::Auth).await.unwrap();
+        storage
+            .record_outcome("spent", &K1Outcome::Succeeded("key".to_string()))
+            .await
+            .unwrap();
+        reserve(&storage, &link(3, 0), "spent", 4_000).await.unwrap();
+        storage.queue_payout("spent", "lnmock-spent", 4_000, Some("faucet")).await.unwrap();
+        storage.claim_payouts(10).await.unwrap();
+        storage.record_channel(&channel("opened", "alice")).await.unwrap();
+    }
+
+    // Reopening runs the migrations again, they must be a no-op
+    let storage = SqliteStorage::open(&path, 10).unwrap();
+    assert_eq!(consume(&storage, "spent", K1Kind::Auth).await, Err(K1Error::Used));
+    assert_eq!(storage.get_k1("spent").await.unwrap().unwrap().outcome.as_deref(), Some("key"));
+    assert_eq!(consume(&storage, "pending", K1Kind::Auth).await, Ok(auth()));
+    assert_eq!(storage.link_usage("faucet").await.unwrap().spent_msat, 4_000);
+    assert_eq!(storage.get_payout("spent").await.unwrap().unwrap().status, PayoutStatus::Paying);
+    assert_eq!(storage.get_channel("opened").await.unwrap(), Some(channel("opened", "alice")));
+
+    drop(storage);
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn session(id: &str, k1: &str, expires_at: u64) -> AuthSession {
+    AuthSession {
+        id: id.to_string(),
+        k1: k1.to_string(),
+        linking_key: "02aa".to_string(),
+        secret_hash: "ff".repeat(32),
+        created_at: 1,
+        expires_at,
+        revoked: false,
+    }
+}
+
+#[tokio::test]
+async fn sessions_are_revoked_and_swept() {
+    for storage in storages(10) {
+        let future = chrono::Utc::now().timestamp() as u64 + 3600;
+        storage.create_session(&session("open", "k1-open", future)).await.unwrap();
+        storage.create_session(&session("expired", "k1-expired", 1)).await.unwrap();
+
+        let open = storage.session_of_k1("k1-open").await.unwrap().unwrap();
+        assert_eq!(open, session("open", "k1-open", future));
+        storage.revoke_session("open").await.unwrap();
+        assert!(storage.get_session("open").await.unwrap().unwrap().revoked);
+
+        assert_eq!(storage.sweep().await.unwrap(), 1);
+        assert!(storage.get_session("expired").await.unwrap().is_none());
+        assert!(storage.get_session("open").await.unwrap().is_some());
+    }
+}
+
+#[tokio::test]
+async fn session_secret_is_drawn_once() {
+    for storage in storages(10) {
+        let secret = storage.session_secret().await.unwrap();
+        assert_eq!(secret.len(), 64);
+        assert_eq!(storage.session_secret().await.unwrap(), secret);
+    }
+
+    let path = std::env::temp_dir().join(format!("lnurl-test-secret-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let secret = SqliteStorage::open(&path, 10).unwrap().session_secret().await.unwrap();
+    // Sessions signed before a restart stay valid
+    assert_eq!(SqliteStorage::open(&path, 10).unwrap().session_secret().await.unwrap(), secret);
+    std::fs::remove_file(&path).unwrap();
+}